@@ -0,0 +1,217 @@
+use std::collections::HashMap;
+use std::ops::Range;
+use crate::cycle;
+use crate::summed_area::{SummedArea, WrappingArea};
+
+// Two-dimensional cellular automata
+//
+// An Automaton is defined by an alphabet of cell states, each drawn as a
+// single character, and a table of transition rules. Each rule changes a
+// cell from one state to another when the counts of states among its
+// eight neighbors (the Moore neighborhood) meet all of the rule's
+// conditions. The first matching rule for a cell applies; a cell with no
+// matching rule keeps its state. Squares beyond the edge of the grid
+// count as the first state of the alphabet, unless the line
+// "edges = wrap" is given, in which case the grid wraps around so that
+// cells on opposite edges are neighbors.
+//
+// Rules are written one per line, for example Conway's Life:
+//
+//     alphabet = .#
+//     # -> # if # >= 2, # <= 3
+//     # -> .
+//     . -> # if # == 3
+//
+// Conditions compare a neighbor count with <, <=, ==, >= or >. Lines
+// starting with // are comments.
+
+type State = u8;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct Condition {
+    state: State,
+    min: usize,
+    max: usize,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+struct Rule {
+    from: State,
+    to: State,
+    conditions: Vec<Condition>,
+}
+
+// How many cells in a rectangle are in one state, along with how many
+// cells of the grid it covers.
+enum Counts {
+    Clipped(SummedArea<usize>),
+    Wrapping(WrappingArea<usize>),
+}
+
+impl Counts {
+    fn count(&self, rows: Range<isize>, cols: Range<isize>) -> (usize, usize) {
+        match self {
+            Counts::Clipped(t) => (t.clipped_sum(rows.clone(), cols.clone()), t.clipped_cells(rows, cols)),
+            Counts::Wrapping(t) => (t.sum(rows.clone(), cols.clone()), t.cells(rows, cols)),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Automaton {
+    alphabet: Vec<char>,
+    rules: Vec<Rule>,
+    wrap: bool,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Cells(Vec<Vec<State>>);
+
+impl Automaton {
+    pub fn parse(s: &str) -> Result<Automaton, String> {
+        let mut alphabet = vec![];
+        let mut rules = vec![];
+        let mut wrap = false;
+        for (lineno, line) in s.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with("//") { continue; }
+            let err = |msg: String| format!("line {}: {}", lineno + 1, msg);
+
+            if let Some(symbols) = line.strip_prefix("alphabet") {
+                let symbols = symbols.trim().trim_start_matches('=').trim();
+                alphabet = symbols.chars().collect();
+                if alphabet.is_empty() || alphabet.len() > State::MAX as usize {
+                    return Err(err("invalid alphabet".to_string()));
+                }
+                continue;
+            }
+
+            if let Some(edges) = line.strip_prefix("edges") {
+                wrap = match edges.trim().trim_start_matches('=').trim() {
+                    "wrap" => true,
+                    "empty" => false,
+                    other => return Err(err(format!("invalid edges {}", other))),
+                };
+                continue;
+            }
+
+            let state = |symbol: &str| {
+                let mut chars = symbol.chars();
+                match (chars.next(), chars.next()) {
+                    (Some(c), None) => alphabet.iter().position(|&a| a == c)
+                        .map(|i| i as State)
+                        .ok_or_else(|| err(format!("{} is not in the alphabet", c))),
+                    _ => Err(err(format!("invalid state {}", symbol))),
+                }
+            };
+
+            let mut parts = line.splitn(2, " if ");
+            let mut arrow = parts.next().unwrap().split("->").map(str::trim);
+            let from = state(arrow.next().unwrap())?;
+            let to = state(arrow.next().ok_or_else(|| err("expected A -> B".to_string()))?)?;
+            let mut conditions = vec![];
+            for cond in parts.next().into_iter().flat_map(|c| c.split(',')) {
+                let words = cond.split_whitespace().collect::<Vec<_>>();
+                if words.len() != 3 {
+                    return Err(err(format!("invalid condition {}", cond.trim())));
+                }
+                let n: usize = words[2].parse().map_err(|_| err(format!("invalid count {}", words[2])))?;
+                let never = || err(format!("{} can never hold", cond.trim()));
+                let (min, max) = match words[1] {
+                    "<"  => (0, n.checked_sub(1).ok_or_else(never)?),
+                    "<=" => (0, n),
+                    "==" => (n, n),
+                    ">=" => (n, 8),
+                    ">"  => (n + 1, 8),
+                    op => return Err(err(format!("invalid comparison {}", op))),
+                };
+                if min > max {
+                    return Err(never());
+                }
+                conditions.push(Condition { state: state(words[0])?, min, max });
+            }
+            rules.push(Rule { from, to, conditions });
+        }
+        if alphabet.is_empty() {
+            return Err("no alphabet given".to_string());
+        }
+        Ok(Automaton { alphabet, rules, wrap })
+    }
+
+    pub fn parse_cells(&self, s: &str) -> Result<Cells, String> {
+        s.lines().map(|line|
+            line.chars().map(|c|
+                self.alphabet.iter().position(|&a| a == c)
+                    .map(|i| i as State)
+                    .ok_or_else(|| format!("{} is not in the alphabet", c))
+            ).collect()
+        ).collect::<Result<_,_>>().map(Cells)
+    }
+
+    pub fn show(&self, cells: &Cells) -> String {
+        cells.0.iter().map(|row|
+            row.iter().map(|&s| self.alphabet[s as usize]).collect::<String>() + "\n"
+        ).collect()
+    }
+
+    pub fn count(&self, cells: &Cells, symbol: char) -> usize {
+        match self.alphabet.iter().position(|&a| a == symbol) {
+            Some(state) => cells.0.iter().flat_map(|row| row.iter())
+                .filter(|&&s| s as usize == state)
+                .count(),
+            None => 0,
+        }
+    }
+
+    pub fn step(&self, cells: &Cells) -> Cells {
+        let mut counted: Vec<State> = self.rules.iter()
+            .flat_map(|r| r.conditions.iter().map(|c| c.state))
+            .collect();
+        counted.sort_unstable();
+        counted.dedup();
+        let height = cells.0.len();
+        let width = cells.0.first().map_or(0, |row| row.len());
+        let tables = counted.iter().map(|&state| {
+            let table = SummedArea::from_fn(height, width, |row, col| (cells.0[row][col] == state) as usize);
+            (state, if self.wrap { Counts::Wrapping(table.wrapping()) } else { Counts::Clipped(table) })
+        }).collect::<HashMap<_,_>>();
+
+        let next = cells.0.iter().enumerate().map(|(row, line)|
+            line.iter().enumerate().map(|(col, &here)| {
+                // Count a state in the 3x3 block centered here, not
+                // counting this cell itself. Squares off the grid are
+                // state 0.
+                let neighbors = |state: State| {
+                    let (r, c) = (row as isize, col as isize);
+                    let table = &tables[&state];
+                    let (mut n, cells) = table.count(r-1..r+2, c-1..c+2);
+                    if state == 0 {
+                        n += 9 - cells;
+                    }
+                    n - (here == state) as usize
+                };
+                self.rules.iter()
+                    .filter(|r| r.from == here)
+                    .find(|r| r.conditions.iter().all(|c| {
+                        let n = neighbors(c.state);
+                        c.min <= n && n <= c.max
+                    }))
+                    .map_or(here, |r| r.to)
+            }).collect()
+        ).collect();
+        Cells(next)
+    }
+
+    pub fn iterate(&self, initial_state: &Cells, steps: usize) -> Cells {
+        let mut state = initial_state.clone();
+        for _t in 0..steps {
+            state = self.step(&state);
+        }
+        state
+    }
+
+    // Iterate many steps, skipping ahead once the states start to repeat.
+    pub fn iterate_long(&self, initial_state: &Cells, steps: usize) -> Cells {
+        cycle::nth(initial_state, |cells| self.step(cells), Cells::clone, steps)
+    }
+}
@@ -0,0 +1,191 @@
+use std::collections::HashMap;
+use std::hash::Hash;
+
+// Cycle detection for iterated functions
+//
+// Starting from x0 and repeatedly applying f gives the sequence
+// x0, x1 = f(x0), x2 = f(x1), ... If the states are drawn from a finite
+// set, the sequence must eventually repeat: after mu steps it enters a
+// cycle of length lambda, so that x(n + lambda) == x(n) for all n >= mu.
+// Knowing mu and lambda, the state at any step can be found without
+// iterating all the way there.
+//
+// Both methods give up if no repeat is found within limit steps.
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Cycle {
+    pub mu: usize,
+    pub lambda: usize,
+}
+
+impl Cycle {
+    // The earliest step with the same state as step n.
+    pub fn equivalent_step(&self, n: usize) -> usize {
+        if n < self.mu {
+            n
+        } else {
+            self.mu + (n - self.mu) % self.lambda
+        }
+    }
+}
+
+// Brent's method. Only two states are kept at any time, so this suits
+// long cycles of small states or states that are too big to keep many
+// copies of. It takes up to about three times as many steps as hashing.
+pub fn brent<S, F>(x0: &S, f: F, limit: usize) -> Option<Cycle>
+    where S: Clone + Eq, F: Fn(&S) -> S
+{
+    // Find the cycle length, comparing against a saved state that is
+    // replaced after 1, 2, 4, 8... steps.
+    let mut power = 1;
+    let mut lambda = 1;
+    let mut tortoise = x0.clone();
+    let mut hare = f(x0);
+    let mut steps = 1;
+    while tortoise != hare {
+        if steps > limit { return None; }
+        if power == lambda {
+            tortoise = hare.clone();
+            power *= 2;
+            lambda = 0;
+        }
+        hare = f(&hare);
+        lambda += 1;
+        steps += 1;
+    }
+
+    // Find the start of the cycle with two states lambda steps apart.
+    let mut tortoise = x0.clone();
+    let mut hare = x0.clone();
+    for _ in 0..lambda {
+        hare = f(&hare);
+    }
+    let mut mu = 0;
+    while tortoise != hare {
+        tortoise = f(&tortoise);
+        hare = f(&hare);
+        mu += 1;
+    }
+    Some(Cycle { mu, lambda })
+}
+
+// What hashed found: the cycle, track(state) for the state at step
+// mu, and the state at step mu + lambda.
+pub struct Repeat<S, V> {
+    pub cycle: Cycle,
+    pub first: V,
+    pub state: S,
+}
+
+// Remember every state seen, keyed by key(state). This takes the fewest
+// steps. It can also find cycles in states that only repeat up to some
+// transformation, such as a pattern that moves as it repeats: let key
+// return the part of the state that repeats (the pattern without its
+// position) and track the part that changes (the position). The change
+// over one cycle is then track(repeat.state) less repeat.first. If there
+// is no repeat within limit steps, the state at step limit is returned
+// instead.
+pub fn hashed<S, K, V, F, G, H>(x0: &S, f: F, key: G, track: H, limit: usize) -> Result<Repeat<S, V>, S>
+    where S: Clone, K: Eq + Hash, F: Fn(&S) -> S, G: Fn(&S) -> K, H: Fn(&S) -> V
+{
+    let mut seen = HashMap::new();
+    let mut state = x0.clone();
+    for t in 0.. {
+        let k = key(&state);
+        if let Some((t0, first)) = seen.remove(&k) {
+            return Ok(Repeat { cycle: Cycle { mu: t0, lambda: t - t0 }, first, state });
+        }
+        if t == limit { break; }
+        seen.insert(k, (t, track(&state)));
+        state = f(&state);
+    }
+    Err(state)
+}
+
+// The state after n steps, skipping ahead once the states start to
+// repeat. Here key must tell all different states apart.
+pub fn nth<S, K, F, G>(x0: &S, f: F, key: G, n: usize) -> S
+    where S: Clone, K: Eq + Hash, F: Fn(&S) -> S, G: Fn(&S) -> K
+{
+    match hashed(x0, &f, key, |_| (), n) {
+        Ok(Repeat { cycle, state, .. }) => {
+            // The state is the same as at step mu.
+            (cycle.mu..cycle.equivalent_step(n)).fold(state, |state, _| f(&state))
+        }
+        Err(state) => state,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Find the cycle by keeping every state in a list.
+    fn naive(x0: u32, f: fn(u32) -> u32) -> Cycle {
+        let mut seen = vec![x0];
+        loop {
+            let x = f(*seen.last().unwrap());
+            if let Some(mu) = seen.iter().position(|&y| y == x) {
+                return Cycle { mu, lambda: seen.len() - mu };
+            }
+            seen.push(x);
+        }
+    }
+
+    #[test]
+    fn both_methods() {
+        let fs: [fn(u32) -> u32; 4] = [
+            |x| (x * x + 1) % 255,
+            |x| (x + 1) % 5,       // mu = 0 from 0
+            |x| (x + 1).min(7),    // lambda = 1
+            |x| x,                 // mu = 0 and lambda = 1
+        ];
+        for &f in fs.iter() {
+            for x0 in 0..20 {
+                let expected = naive(x0, f);
+                assert_eq!(Some(expected), brent(&x0, |&x| f(x), usize::MAX), "x0 = {}", x0);
+                let repeat = hashed(&x0, |&x| f(x), |&x| x, |_| (), usize::MAX).ok().unwrap();
+                assert_eq!(expected, repeat.cycle, "x0 = {}", x0);
+                let steps = expected.mu + expected.lambda;
+                assert_eq!((0..steps).fold(x0, |x, _| f(x)), repeat.state, "x0 = {}", x0);
+            }
+        }
+        assert_eq!(Cycle { mu: 0, lambda: 5 }, hashed(&0, |&x| (x + 1) % 5, |&x| x, |_| (), 100).ok().unwrap().cycle);
+        assert_eq!(Some(Cycle { mu: 7, lambda: 1 }), brent(&0, |&x| (x + 1).min(7), 100));
+        assert_eq!(Some(Cycle { mu: 0, lambda: 1 }), brent(&3, |&x| x, 0));
+    }
+
+    #[test]
+    fn limits() {
+        // The repeat shows up at step 5.
+        let f = |&x: &u32| (x + 1) % 5;
+        assert_eq!(Cycle { mu: 0, lambda: 5 }, hashed(&0, f, |&x| x, |_| (), 5).ok().unwrap().cycle);
+        assert_eq!(None, brent(&0, f, 3));
+        assert_eq!(4, hashed(&0, f, |&x| x, |_| (), 4).err().unwrap());
+    }
+
+    #[test]
+    fn skipping_ahead() {
+        let f = |&x: &u32| (x * x + 1) % 255;
+        let mut x = 6;
+        for n in 0..300 {
+            assert_eq!(x, nth(&6, f, |&x| x, n), "n = {}", n);
+            x = f(&x);
+        }
+        assert_eq!(0, nth(&0, |&x| (x + 1) % 5, |&x| x, 1_000_000_000));
+    }
+
+    #[test]
+    fn moving_pattern() {
+        // A pattern that moves 3 places every 2 steps once it settles.
+        let f = |&(pattern, pos): &(u32, i64)| match pattern {
+            0 => (1, pos),
+            1 => (2, pos + 1),
+            _ => (1, pos + 2),
+        };
+        let repeat = hashed(&(0, 10), f, |s| s.0, |s| s.1, 100).ok().unwrap();
+        assert_eq!(Cycle { mu: 1, lambda: 2 }, repeat.cycle);
+        assert_eq!(10, repeat.first);
+        assert_eq!((1, 13), repeat.state);
+    }
+}
@@ -1,41 +1,333 @@
+use std::fmt::Write;
+use std::fs::{self, File};
+use std::io::{self, BufReader, Read};
+use crate::options::Options;
+
 fn parse_input(s: &str) -> &str {
     s.trim()
 }
 
-fn test_react(a: u8, b: u8) -> bool {
-    a != b && a.to_ascii_lowercase() == b.to_ascii_lowercase()
+// A reaction replaces some units next to each other, the pattern, with
+// a product. The product is usually nothing, and must be shorter than the
+// pattern, so that reducing always comes to an end.
+#[derive(Clone, Debug, PartialEq)]
+struct Reaction {
+    pattern: Vec<u8>,
+    product: Vec<u8>,
+}
+
+// A table of reactions, read from lines like these:
+//
+//     // units of the same type and opposite polarity
+//     opposite-case
+//     // any other units
+//     xy ->
+//     abc -> d
+//
+// Where more than one reaction could happen, the first in the table wins.
+#[derive(Clone, Debug)]
+struct Rules {
+    reactions: Vec<Reaction>,
+    // The reactions for each unit, by the last unit of their pattern.
+    by_last: Vec<Vec<usize>>,
 }
 
-fn collapsed_len_i<I: Iterator<Item=u8>>(polymer: I) -> usize {
-    let mut units = vec![];
-    for u in polymer {
-        match units.last() {
-            Some(&last_u) if test_react(last_u, u) => { units.pop(); },
-            _ => units.push(u),
+impl Rules {
+    fn new(reactions: Vec<Reaction>) -> Rules {
+        let mut by_last = vec![vec![]; 256];
+        for (i, r) in reactions.iter().enumerate() {
+            by_last[*r.pattern.last().unwrap() as usize].push(i);
         }
+        Rules { reactions, by_last }
     }
-    units.len()
+
+    fn opposite_case() -> Vec<Reaction> {
+        (b'a'..=b'z').flat_map(|u| {
+            let upper = u.to_ascii_uppercase();
+            vec![
+                Reaction { pattern: vec![u, upper], product: vec![] },
+                Reaction { pattern: vec![upper, u], product: vec![] },
+            ]
+        }).collect()
+    }
+
+    fn parse(s: &str) -> Result<Rules, String> {
+        let mut reactions = vec![];
+        for (lineno, line) in s.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with("//") { continue; }
+            let err = |msg: &str| format!("line {}: {}", lineno + 1, msg);
+            if line == "opposite-case" {
+                reactions.extend(Rules::opposite_case());
+                continue;
+            }
+            let mut parts = line.splitn(2, "->").map(str::trim);
+            let pattern = parts.next().unwrap().as_bytes().to_vec();
+            let product = parts.next().ok_or_else(|| err("expected PATTERN -> PRODUCT"))?
+                .as_bytes().to_vec();
+            if pattern.is_empty() {
+                return Err(err("empty pattern"));
+            }
+            if product.len() >= pattern.len() {
+                return Err(err("the product must be shorter than the pattern"));
+            }
+            reactions.push(Reaction { pattern, product });
+        }
+        Ok(Rules::new(reactions))
+    }
+
+    // The reaction that happens at the end of some units, if any.
+    fn reaction_at_end(&self, units: &[u8]) -> Option<&Reaction> {
+        let last = *units.last()?;
+        self.by_last[last as usize].iter()
+            .map(|&i| &self.reactions[i])
+            .find(|r| units.ends_with(&r.pattern))
+    }
+
+    // Whether these are just the puzzle's reactions. Then taking out a unit
+    // type gives the same result whether or not the polymer was reduced
+    // first.
+    fn is_opposite_case(&self) -> bool {
+        self.reactions == Rules::opposite_case()
+    }
+}
+
+impl Default for Rules {
+    fn default() -> Rules {
+        Rules::new(Rules::opposite_case())
+    }
+}
+
+fn unit_type(u: u8) -> u8 {
+    u.to_ascii_lowercase()
+}
+
+// Reduces a polymer as it arrives. Each unit is added to the units left
+// so far, and if that lets a reaction happen, its pattern is taken off
+// the end and its product added back, one unit at a time.
+struct Reducer<'a> {
+    rules: &'a Rules,
+    units: Vec<u8>,
 }
 
-fn collapsed_len(polymer: &str, ignore: Option<u8>) -> usize {
-    if let Some(ig) = ignore {
-        collapsed_len_i(polymer.bytes().filter(|u| u.to_ascii_lowercase() != ig))
+impl<'a> Reducer<'a> {
+    fn new(rules: &'a Rules) -> Self {
+        Reducer { rules, units: vec![] }
+    }
+
+    fn extend<I: IntoIterator<Item=u8>>(&mut self, polymer: I) {
+        let mut pending = vec![];
+        for u in polymer {
+            pending.push(u);
+            while let Some(u) = pending.pop() {
+                self.units.push(u);
+                if let Some(r) = self.rules.reaction_at_end(&self.units) {
+                    let remaining = self.units.len() - r.pattern.len();
+                    self.units.truncate(remaining);
+                    pending.extend(r.product.iter().rev());
+                }
+            }
+        }
+    }
+}
+
+const CHUNK_SIZE: usize = 64 * 1024;
+
+// Reduce a polymer read in chunks, so that only the reduced polymer is
+// ever held in memory. Whitespace is skipped.
+fn reduce_reader<R: Read>(mut reader: R, rules: &Rules) -> io::Result<Vec<u8>> {
+    let mut reducer = Reducer::new(rules);
+    let mut chunk = vec![0; CHUNK_SIZE];
+    loop {
+        let n = match reader.read(&mut chunk) {
+            Ok(0) => break,
+            Ok(n) => n,
+            Err(ref err) if err.kind() == io::ErrorKind::Interrupted => continue,
+            Err(err) => return Err(err),
+        };
+        reducer.extend(chunk[..n].iter().cloned().filter(|u| !u.is_ascii_whitespace()));
+    }
+    Ok(reducer.units)
+}
+
+fn reduce_without(polymer: &[u8], ignore: u8, rules: &Rules) -> Vec<u8> {
+    let mut reducer = Reducer::new(rules);
+    reducer.extend(polymer.iter().cloned().filter(|&u| unit_type(u) != ignore));
+    reducer.units
+}
+
+// The reactions as a tree, as built by collapse in
+// visualizations/polymer.js. The trunk is the reduced polymer, after a
+// root node with unit '0'. When a unit sets off a reaction, the other
+// units of the pattern are attached under the unit before them, along with
+// everything that reacted on top of them, and the product carries on
+// along the trunk. Ignored units are attached where they would have been.
+struct ReactionNode {
+    // None for an ignored unit.
+    unit: Option<u8>,
+    subnodes: Vec<usize>,
+}
+
+struct ReactionTree {
+    nodes: Vec<ReactionNode>,
+    trunk: Vec<usize>,
+}
+
+impl ReactionTree {
+    fn new(polymer: &[u8], ignore: Option<u8>, rules: &Rules) -> Self {
+        let mut nodes = vec![ReactionNode { unit: Some(b'0'), subnodes: vec![] }];
+        let mut trunk = vec![0];
+        // The units of the trunk after the root, as a Reducer would hold them.
+        let mut units = vec![];
+        let mut pending = vec![];
+        for &u in polymer {
+            if ignore == Some(unit_type(u)) {
+                nodes.push(ReactionNode { unit: None, subnodes: vec![] });
+                let id = nodes.len() - 1;
+                nodes[*trunk.last().unwrap()].subnodes.push(id);
+                continue;
+            }
+            pending.push(u);
+            while let Some(u) = pending.pop() {
+                units.push(u);
+                match rules.reaction_at_end(&units) {
+                    Some(r) => {
+                        units.truncate(units.len() - r.pattern.len());
+                        let reacted = trunk.split_off(trunk.len() + 1 - r.pattern.len());
+                        nodes[*trunk.last().unwrap()].subnodes.extend(reacted);
+                        pending.extend(r.product.iter().rev());
+                    }
+                    None => {
+                        nodes.push(ReactionNode { unit: Some(u), subnodes: vec![] });
+                        trunk.push(nodes.len() - 1);
+                    }
+                }
+            }
+        }
+        ReactionTree { nodes, trunk }
+    }
+
+    // The trunk as a JSON array, written without recursion since the
+    // tree can be very deep.
+    fn to_json(&self) -> String {
+        let mut json = String::from("[");
+        // Lists of nodes still to write, with how far each has got.
+        let mut lists: Vec<(&[usize], usize)> = vec![(&self.trunk, 0)];
+        while let Some((list, i)) = lists.pop() {
+            if i == list.len() {
+                json.push_str(if lists.is_empty() { "]" } else { "]}" });
+                continue;
+            }
+            if i > 0 {
+                json.push(',');
+            }
+            let node = &self.nodes[list[i]];
+            match node.unit {
+                Some(u) => write!(json, "{{\"unit\":{},\"subnodes\":[", json_string(&[u])).unwrap(),
+                None => json.push_str("{\"ignore\":true,\"subnodes\":["),
+            }
+            lists.push((list, i + 1));
+            lists.push((&node.subnodes, 0));
+        }
+        json
+    }
+}
+
+// Units as a JSON string, which also works as a JavaScript string. Rule
+// tables allow any byte as a unit, so quotes, backslashes and anything
+// outside printable ASCII are escaped.
+fn json_string(units: &[u8]) -> String {
+    let mut s = String::from("\"");
+    for &u in units {
+        match u {
+            b'"' => s.push_str("\\\""),
+            b'\\' => s.push_str("\\\\"),
+            0x20..=0x7e => s.push(char::from(u)),
+            _ => write!(s, "\\u{:04x}", u).unwrap(),
+        }
+    }
+    s.push('"');
+    s
+}
+
+// Save the reaction tree for visualizations/day05.html, or as plain JSON
+// for any file not ending in .js. The viewer only knows the puzzle's
+// rules, so it rebuilds the tree with those if the polymer or the removed
+// unit is changed there.
+fn export_tree(polymer: &str, ignore: Option<u8>, rules: &Rules, path: &str) -> io::Result<()> {
+    let tree = ReactionTree::new(polymer.as_bytes(), ignore, rules);
+    if path.ends_with(".js") {
+        let ignore = ignore.map_or(vec![], |u| vec![u]);
+        fs::write(path, format!("const POLYMER = {};\nconst POLYMER_IGNORE = {};\nconst POLYMER_TREE = {};\n",
+                                json_string(polymer.as_bytes()), json_string(&ignore), tree.to_json()))
     } else {
-        collapsed_len_i(polymer.bytes())
+        fs::write(path, tree.to_json() + "\n")
     }
 }
 
-fn solve(input: &str) -> (usize, usize) {
-    let polymer = parse_input(input);
-    let part1 = collapsed_len(polymer, None);
-    let shortest = (b'a'..=b'z').map(|ignore| collapsed_len(polymer, Some(ignore))).min().unwrap();
-    (part1, shortest)
+fn solve_with(input: &str, rules: &Rules) -> (usize, usize) {
+    let reduced = reduce_reader(input.as_bytes(), rules).unwrap();
+    // When the shortcut holds, start from the shorter, reduced polymer.
+    let polymer = if rules.is_opposite_case() {
+        reduced.clone()
+    } else {
+        parse_input(input).bytes().filter(|u| !u.is_ascii_whitespace()).collect()
+    };
+    let mut types = polymer.iter().map(|&u| unit_type(u)).collect::<Vec<_>>();
+    types.sort_unstable();
+    types.dedup();
+    let shortest = types.iter()
+        .map(|&ignore| reduce_without(&polymer, ignore, rules).len())
+        .min().unwrap_or(0);
+    (reduced.len(), shortest)
 }
 
-pub fn run(input: &str) {
-    let (part1, part2) = solve(input);
-    println!("the solution to part 1 is {}", part1);
-    println!("the solution to part 2 is {}", part2);
+pub fn run(input: &str, options: &[String]) {
+    if let Err(err) = run_options(input, Options::new(options)) {
+        eprintln!("{}", err);
+    }
+}
+
+fn run_options(input: &str, options: Options) -> Result<(), String> {
+    let (rules, options) = match options.mode() {
+        Some("rules") => {
+            let path = options.required(1, "rules file")?;
+            let rules = fs::read_to_string(path)
+                .map_err(|err| err.to_string())
+                .and_then(|s| Rules::parse(&s))
+                .map_err(|err| format!("could not load rules from {}: {}", path, err))?;
+            (rules, options.rest().rest())
+        }
+        _ => (Rules::default(), options),
+    };
+
+    match options.mode() {
+        Some("tree") => {
+            let path = options.required(1, "file name")?;
+            let ignore = options.get(2).map(|unit| match *unit.as_bytes() {
+                [u] => Ok(unit_type(u)),
+                _ => Err(format!("invalid unit: {}", unit)),
+            }).transpose()?;
+            export_tree(parse_input(input), ignore, &rules, path)
+                .map_err(|err| format!("could not write {}: {}", path, err))?;
+        }
+        Some("stream") => {
+            // For polymers too big to load whole: only the reduced polymer
+            // is kept in memory.
+            let path = options.required(1, "file name")?;
+            let reduced = File::open(path)
+                .and_then(|file| reduce_reader(BufReader::new(file), &rules))
+                .map_err(|err| format!("could not read {}: {}", path, err))?;
+            println!("the reduced polymer has {} units", reduced.len());
+        }
+        Some(_) => return Err(options.unknown()),
+        None => {
+            let (part1, part2) = solve_with(input, &rules);
+            println!("the solution to part 1 is {}", part1);
+            println!("the solution to part 2 is {}", part2);
+        }
+    }
+    Ok(())
 }
 
 #[cfg(test)]
@@ -44,6 +336,10 @@ mod tests {
 
     const EXAMPLE : &'static str = "dabAcCaCBAcCcaDA\n";
 
+    fn solve(input: &str) -> (usize, usize) {
+        solve_with(input, &Rules::default())
+    }
+
     #[test]
     fn parsing() {
         assert_eq!(parse_input(EXAMPLE), "dabAcCaCBAcCcaDA");
@@ -56,6 +352,123 @@ mod tests {
         assert_eq!(4, part2);
     }
 
+    // A reader that hands over a few bytes at a time.
+    struct Trickle<'a>(&'a [u8], usize);
+
+    impl<'a> Read for Trickle<'a> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let n = self.1.min(buf.len()).min(self.0.len());
+            buf[..n].copy_from_slice(&self.0[..n]);
+            self.0 = &self.0[n..];
+            Ok(n)
+        }
+    }
+
+    #[test]
+    fn streaming() {
+        let rules = Rules::default();
+        for size in 1..5 {
+            let reduced = reduce_reader(Trickle(EXAMPLE.as_bytes(), size), &rules).unwrap();
+            assert_eq!(reduced, b"dabCBAcaDA");
+        }
+        assert_eq!(reduce_without(b"dabCBAcaDA", b'c', &rules), b"daDA");
+        assert_eq!(reduce_without(b"dabCBAcaDA", b'c', &rules).len(),
+                   reduce_reader("dabAcCaCBAcCcaDA".replace(&['c', 'C'][..], "").as_bytes(), &rules).unwrap().len());
+
+        // More than one chunk.
+        let long = "aBcD".repeat(CHUNK_SIZE) + &"dCbA".repeat(CHUNK_SIZE - 1);
+        assert_eq!(reduce_reader(long.as_bytes(), &rules).unwrap(), b"aBcD");
+    }
+
+    #[test]
+    fn reaction_tree() {
+        // The same trees as collapse in visualizations/polymer.js makes.
+        let tree = ReactionTree::new(b"dabAcCaCBAcCcaDA", None, &Rules::default());
+        assert_eq!(tree.to_json(), "[\
+{\"unit\":\"0\",\"subnodes\":[]},\
+{\"unit\":\"d\",\"subnodes\":[]},\
+{\"unit\":\"a\",\"subnodes\":[]},\
+{\"unit\":\"b\",\"subnodes\":[\
+{\"unit\":\"A\",\"subnodes\":[{\"unit\":\"c\",\"subnodes\":[]}]}]},\
+{\"unit\":\"C\",\"subnodes\":[]},\
+{\"unit\":\"B\",\"subnodes\":[]},\
+{\"unit\":\"A\",\"subnodes\":[{\"unit\":\"c\",\"subnodes\":[]}]},\
+{\"unit\":\"c\",\"subnodes\":[]},\
+{\"unit\":\"a\",\"subnodes\":[]},\
+{\"unit\":\"D\",\"subnodes\":[]},\
+{\"unit\":\"A\",\"subnodes\":[]}]");
+        let tree = ReactionTree::new(b"dAaBcCb", Some(b'c'), &Rules::default());
+        assert_eq!(tree.to_json(), "[\
+{\"unit\":\"0\",\"subnodes\":[]},\
+{\"unit\":\"d\",\"subnodes\":[{\"unit\":\"A\",\"subnodes\":[]},\
+{\"unit\":\"B\",\"subnodes\":[{\"ignore\":true,\"subnodes\":[]},{\"ignore\":true,\"subnodes\":[]}]}]}]");
+        assert_eq!(tree.trunk.len(), 2);
+
+        // Units that need escaping.
+        let tree = ReactionTree::new(b"\"\\'\n", None, &Rules::default());
+        assert_eq!(tree.to_json(), "[\
+{\"unit\":\"0\",\"subnodes\":[]},\
+{\"unit\":\"\\\"\",\"subnodes\":[]},\
+{\"unit\":\"\\\\\",\"subnodes\":[]},\
+{\"unit\":\"'\",\"subnodes\":[]},\
+{\"unit\":\"\\u000a\",\"subnodes\":[]}]");
+        assert_eq!(json_string(b"aB\xe9"), "\"aB\\u00e9\"");
+
+        // Other rules: abc turns into d, which reacts with the next d.
+        let rules = Rules::parse("abc -> d\ndd ->\n").unwrap();
+        let tree = ReactionTree::new(b"xabcdy", None, &rules);
+        assert_eq!(tree.to_json(), "[\
+{\"unit\":\"0\",\"subnodes\":[]},\
+{\"unit\":\"x\",\"subnodes\":[\
+{\"unit\":\"a\",\"subnodes\":[]},{\"unit\":\"b\",\"subnodes\":[]},{\"unit\":\"d\",\"subnodes\":[]}]},\
+{\"unit\":\"y\",\"subnodes\":[]}]");
+    }
+
+    #[test]
+    fn rule_tables() {
+        let reduce = |polymer: &str, rules: &Rules| {
+            String::from_utf8(reduce_reader(polymer.as_bytes(), rules).unwrap()).unwrap()
+        };
+        let standard = Rules::parse("// the puzzle's rules\nopposite-case\n").unwrap();
+        assert_eq!(solve_with(EXAMPLE, &standard), (10, 4));
+        assert!(standard.is_opposite_case());
+
+        // Pairs of any units, in either order if listed both ways.
+        let pairs = Rules::parse("+- ->\n-+ ->\n() ->\n").unwrap();
+        assert_eq!(reduce("(+(-+)-)(", &pairs), "(");
+        assert!(!pairs.is_opposite_case());
+
+        // Longer patterns, and products that can react again.
+        let chem = Rules::parse("abc -> d\ndd -> e\nea ->\n").unwrap();
+        assert_eq!(reduce("abcabc", &chem), "e");
+        assert_eq!(reduce("abcabcabab", &chem), "bab");
+        assert_eq!(reduce("xabcx", &chem), "xdx");
+
+        // The first reaction listed wins.
+        let first = Rules::parse("ab -> c\nb ->\n").unwrap();
+        assert_eq!(reduce("abb", &first), "c");
+
+        // With these rules, taking out a unit type after reducing isn't
+        // the same as taking it out first: abb reduces to b, but without
+        // the a it is bb.
+        let rules = Rules::parse("ab ->\n").unwrap();
+        assert_eq!(solve_with("abb", &rules), (1, 1));
+        assert_eq!(solve_with("", &rules), (0, 0));
+
+        // Reactions between units of one type aren't enough either: axaAA
+        // reduces to axA, and taking out the x from that leaves aA to
+        // react, but from the whole polymer it leaves aaAA, which only
+        // gets down to AA. Taking out x or a leaves at least one unit.
+        let rules = Rules::parse("aa ->\naA ->\n").unwrap();
+        assert!(!rules.is_opposite_case());
+        assert_eq!(solve_with("axaAA", &rules), (3, 1));
+        assert!(!Rules::parse("opposite-case\nxy ->\n").unwrap().is_opposite_case());
+
+        assert!(Rules::parse("ab -> cd\n").is_err());
+        assert!(Rules::parse(" -> \n").is_err());
+        assert!(Rules::parse("ab\n").is_err());
+    }
+
     #[cfg(feature="test_real_input")]
     #[test]
     fn real_input() {
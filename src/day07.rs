@@ -2,6 +2,10 @@ use std::collections::HashMap;
 use std::collections::HashSet;
 use std::collections::BinaryHeap;
 use std::cmp::Reverse;
+use std::fmt::Display;
+use std::fs;
+use std::hash::Hash;
+use crate::options::Options;
 
 fn parse_input(s: &str) -> Vec<(u8, u8)> {
     s.lines().map(|line| {
@@ -10,53 +14,120 @@ fn parse_input(s: &str) -> Vec<(u8, u8)> {
     }).collect()
 }
 
-fn init_ready(task_spec: &[(u8, u8)]) -> BinaryHeap<Reverse<u8>> {
-    let mut pres = HashSet::new();
-    let mut deps = HashSet::new();
-    for &(pre, dep) in task_spec.iter() {
-        pres.insert(pre);
-        deps.insert(dep);
+// The steps of a task list, and pairs of steps where the first must be
+// finished before the second can begin.
+#[derive(Clone, Debug, PartialEq)]
+struct TaskGraph<T> {
+    tasks: Vec<T>,
+    deps: Vec<(T, T)>,
+}
+
+impl<T: Clone + Ord> TaskGraph<T> {
+    fn from_deps(deps: &[(T, T)]) -> Self {
+        let mut tasks = deps.iter().flat_map(|(pre, dep)| vec![pre.clone(), dep.clone()]).collect::<Vec<_>>();
+        tasks.sort();
+        tasks.dedup();
+        TaskGraph { tasks, deps: deps.to_vec() }
     }
-    pres.difference(&deps).map(|&s| Reverse(s)).collect()
 }
 
-fn init_pending(task_spec: &[(u8, u8)]) -> HashMap<u8, HashSet<u8>> {
+// Parse a task list with any step names, where steps can also be given
+// durations with lines like "Step A takes 5 seconds." A step that is
+// only mentioned there has no dependencies.
+fn parse_tasks(s: &str) -> Result<(TaskGraph<String>, HashMap<String, usize>), String> {
+    let mut deps = vec![];
+    let mut durations = HashMap::new();
+    for line in s.lines().filter(|line| !line.trim().is_empty()) {
+        let words = line.split_whitespace().collect::<Vec<_>>();
+        match words.as_slice() {
+            ["Step", pre, "must", "be", "finished", "before", "step", dep, "can", "begin."] =>
+                deps.push((pre.to_string(), dep.to_string())),
+            ["Step", task, "takes", n, _] => {
+                let n = n.parse().map_err(|_| format!("invalid duration: {}", line))?;
+                durations.insert(task.to_string(), n);
+            }
+            _ => return Err(format!("invalid line: {}", line)),
+        }
+    }
+    let mut graph = TaskGraph::from_deps(&deps);
+    graph.tasks.extend(durations.keys().cloned());
+    graph.tasks.sort();
+    graph.tasks.dedup();
+    Ok((graph, durations))
+}
+
+// The time for a step in the puzzle: 60 seconds plus its letter's place
+// in the alphabet.
+fn puzzle_time(task: &str) -> Option<usize> {
+    match task.as_bytes() {
+        &[c] if c.is_ascii_uppercase() => Some((c - b'A') as usize + 61),
+        _ => None,
+    }
+}
+
+fn init_ready<T: Clone + Ord + Hash>(graph: &TaskGraph<T>) -> BinaryHeap<Reverse<T>> {
+    let deps = graph.deps.iter().map(|(_, dep)| dep).collect::<HashSet<_>>();
+    graph.tasks.iter().filter(|t| !deps.contains(t)).map(|t| Reverse(t.clone())).collect()
+}
+
+fn init_pending<T: Clone + Eq + Hash>(graph: &TaskGraph<T>) -> HashMap<T, HashSet<T>> {
     let mut pending = HashMap::new();
-    for &(pre, dep) in task_spec.iter() {
-        (*pending.entry(dep).or_insert_with(HashSet::new)).insert(pre);
+    for (pre, dep) in graph.deps.iter() {
+        (*pending.entry(dep.clone()).or_insert_with(HashSet::new)).insert(pre.clone());
     }
     pending
 }
 
-fn execute<F>(task_spec: &[(u8,u8)], workers: u32, step_time_f: F) -> (String, usize)
-    where F: Fn(u8) -> usize
+// A step done by one worker from start until end.
+#[derive(Clone, Debug, PartialEq)]
+struct Assignment<T> {
+    task: T,
+    worker: usize,
+    start: usize,
+    end: usize,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+struct Schedule<T> {
+    workers: usize,
+    // In the order the steps were finished.
+    assignments: Vec<Assignment<T>>,
+    total_time: usize,
+}
+
+// Run the steps with some number of workers. Whenever a worker is free
+// it starts the first ready step in order, and free workers are chosen
+// lowest number first. If the steps can't all be done, the error is a
+// cycle of steps that depend on each other.
+fn schedule<T, F>(graph: &TaskGraph<T>, workers: usize, step_time_f: F) -> Result<Schedule<T>, Vec<T>>
+    where T: Clone + Ord + Hash, F: Fn(&T) -> usize
 {
-    let mut ready = init_ready(task_spec);
-    let mut pending = init_pending(task_spec);
+    let mut ready = init_ready(graph);
+    let mut pending = init_pending(graph);
     let mut in_progress = BinaryHeap::new();
-    let mut workers_avail = workers;
+    let mut workers_avail = (0..workers).map(Reverse).collect::<BinaryHeap<_>>();
     let mut t = 0;
-    let mut result = String::new();
+    let mut assignments = vec![];
 
     while !ready.is_empty() || !in_progress.is_empty() {
         // Start jobs
-        while workers_avail > 0 {
+        while !workers_avail.is_empty() {
             if let Some(Reverse(step)) = ready.pop() {
-                in_progress.push(Reverse((t + step_time_f(step), step)));
-                workers_avail -= 1;
+                let Reverse(worker) = workers_avail.pop().unwrap();
+                in_progress.push(Reverse((t + step_time_f(&step), step, worker, t)));
             } else {
                 break;
             }
         }
 
         // Advance time; finish next job
-        if let Some(Reverse((next_t, completed))) = in_progress.pop() {
+        if let Some(Reverse((next_t, completed, worker, start))) = in_progress.pop() {
             t = next_t;
-            result.push(char::from(completed));
-            workers_avail += 1;
+            workers_avail.push(Reverse(worker));
             for prereqs in pending.values_mut() {
                 prereqs.remove(&completed);
             }
+            assignments.push(Assignment { task: completed, worker, start, end: t });
         }
 
         // Ready new jobs
@@ -66,7 +137,184 @@ fn execute<F>(task_spec: &[(u8,u8)], workers: u32, step_time_f: F) -> (String, u
         pending = still_pending;
     }
 
-    (result, t)
+    if pending.is_empty() {
+        Ok(Schedule { workers, assignments, total_time: t })
+    } else {
+        Err(find_cycle(&pending))
+    }
+}
+
+// Every step left pending is waiting on another pending step, so
+// following those waits from any of them must come round in a loop. The
+// loop is returned in dependency order, starting from its first step.
+fn find_cycle<T: Clone + Ord + Hash>(pending: &HashMap<T, HashSet<T>>) -> Vec<T> {
+    let mut path = vec![pending.keys().min().unwrap().clone()];
+    loop {
+        let next = pending[path.last().unwrap()].iter().min().unwrap();
+        if let Some(i) = path.iter().position(|t| t == next) {
+            let mut cycle = path.split_off(i);
+            cycle.reverse();
+            let first = (0..cycle.len()).min_by_key(|&i| &cycle[i]).unwrap();
+            cycle.rotate_left(first);
+            return cycle;
+        }
+        path.push(next.clone());
+    }
+}
+
+fn execute<F>(task_spec: &[(u8,u8)], workers: u32, step_time_f: F) -> (String, usize)
+    where F: Fn(u8) -> usize
+{
+    let graph = TaskGraph::from_deps(task_spec);
+    match schedule(&graph, workers as usize, |&step| step_time_f(step)) {
+        Ok(schedule) => {
+            let order = schedule.assignments.iter().map(|a| char::from(a.task)).collect();
+            (order, schedule.total_time)
+        }
+        Err(cycle) => panic!("steps {} depend on each other", String::from_utf8_lossy(&cycle)),
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+struct Report {
+    // The chain of steps that takes longest, which no number of workers
+    // can speed up.
+    critical_path: Vec<u8>,
+    critical_time: usize,
+    // Each step with its earliest start and its slack: how long it can be
+    // put off without delaying the end, given enough workers.
+    slack: Vec<(u8, usize, usize)>,
+    // The total time with 1, 2, 3... workers.
+    completion_times: Vec<usize>,
+}
+
+fn report<F>(task_spec: &[(u8,u8)], max_workers: u32, step_time_f: F) -> Report
+    where F: Fn(u8) -> usize
+{
+    // With a worker for every step, each starts as soon as it's ready,
+    // so the steps finish in a topological order and the total time is
+    // the length of the critical path.
+    let graph = TaskGraph::from_deps(task_spec);
+    let (order, critical_time) = execute(task_spec, graph.tasks.len() as u32, &step_time_f);
+    let order = order.into_bytes();
+
+    let mut earliest = HashMap::new();
+    for &step in order.iter() {
+        let start = task_spec.iter().filter(|&&(_, dep)| dep == step)
+            .map(|&(pre, _)| earliest[&pre] + step_time_f(pre))
+            .max().unwrap_or(0);
+        earliest.insert(step, start);
+    }
+    let mut latest = HashMap::new();
+    for &step in order.iter().rev() {
+        let end = task_spec.iter().filter(|&&(pre, _)| pre == step)
+            .map(|&(_, dep)| latest[&dep])
+            .min().unwrap_or(critical_time);
+        latest.insert(step, end - step_time_f(step));
+    }
+    let slack = graph.tasks.iter()
+        .map(|step| (*step, earliest[step], latest[step] - earliest[step]))
+        .collect::<Vec<_>>();
+
+    // Follow steps with no slack, each starting as the last one ends.
+    let critical = |step: &u8| latest[step] == earliest[step];
+    let mut critical_path = graph.tasks.iter().cloned()
+        .filter(|s| critical(s) && earliest[s] == 0)
+        .take(1)
+        .collect::<Vec<_>>();
+    while let Some(&last) = critical_path.last() {
+        let end = earliest[&last] + step_time_f(last);
+        let next = graph.tasks.iter()
+            .find(|&&s| critical(&s) && earliest[&s] == end && task_spec.contains(&(last, s)));
+        match next {
+            Some(&next) => critical_path.push(next),
+            None => break,
+        }
+    }
+
+    let completion_times = (1..=max_workers).map(|workers| execute(task_spec, workers, &step_time_f).1).collect();
+    Report { critical_path, critical_time, slack, completion_times }
+}
+
+fn show_report(report: &Report) -> String {
+    let path = report.critical_path.iter().map(|&s| char::from(s).to_string()).collect::<Vec<_>>();
+    let mut out = format!("critical path: {} ({} seconds)\n", path.join(" -> "), report.critical_time);
+    out += "step  earliest start  slack\n";
+    for &(step, start, slack) in report.slack.iter() {
+        out += &format!("{:>4}  {:>14}  {:>5}\n", char::from(step), start, slack);
+    }
+    out += "workers  time\n";
+    for (i, time) in report.completion_times.iter().enumerate() {
+        out += &format!("{:>7}  {:>4}\n", i + 1, time);
+    }
+    let best = report.completion_times.iter().min().unwrap();
+    let enough = report.completion_times.iter().position(|t| t == best).unwrap() + 1;
+    out += &format!("more than {} workers don't help\n", enough);
+    out
+}
+
+// Draw each worker's steps as a row of text, with each character
+// standing for some number of seconds. A step's bar starts with its
+// name, cut short if the bar is; steps much shorter than a character may
+// not show at all.
+fn gantt_text<T: Display>(schedule: &Schedule<T>, seconds_per_char: usize) -> String {
+    let columns = |t: usize| (t + seconds_per_char / 2) / seconds_per_char;
+    let width = columns(schedule.total_time);
+    let mut rows = vec![vec!['.'; width]; schedule.workers];
+    for a in schedule.assignments.iter() {
+        let name = a.task.to_string();
+        let bar = name.chars().chain(std::iter::repeat('-'));
+        for (col, c) in (columns(a.start)..columns(a.end)).zip(bar) {
+            rows[a.worker][col] = c;
+        }
+    }
+    let label_width = (schedule.workers.max(1) - 1).to_string().len();
+    rows.iter().enumerate().map(|(worker, row)|
+        format!("worker {:>w$} |{}|\n", worker, row.iter().collect::<String>(), w = label_width)
+    ).collect()
+}
+
+// Step names can be any word, so they may hold characters that mean
+// something in HTML.
+fn escape_html(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+// The same chart as a web page with an SVG drawing. Hovering over a bar
+// shows the step and its times.
+fn gantt_html<T: Display>(schedule: &Schedule<T>) -> String {
+    const ROW: usize = 30;
+    const LABEL: usize = 80;
+    let scale = (1000.0 / schedule.total_time.max(1) as f64).min(20.0);
+    let x = |t: usize| LABEL as f64 + t as f64 * scale;
+    let height = ROW * schedule.workers + 20;
+    let mut svg = format!("<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{}\" height=\"{}\" font-family=\"sans-serif\" font-size=\"12\">\n",
+                          x(schedule.total_time).ceil() + 10.0, height);
+    for worker in 0..schedule.workers {
+        svg += &format!("  <text x=\"0\" y=\"{}\">worker {}</text>\n", worker * ROW + 19, worker);
+    }
+    for a in schedule.assignments.iter() {
+        let y = a.worker * ROW + 4;
+        let task = escape_html(&a.task.to_string());
+        svg += &format!("  <g><title>{} ({} to {})</title>\n", task, a.start, a.end);
+        svg += &format!("    <rect x=\"{:.1}\" y=\"{}\" width=\"{:.1}\" height=\"{}\" fill=\"#8cb4e0\" stroke=\"#204060\"/>\n",
+                        x(a.start), y, x(a.end) - x(a.start), ROW - 8);
+        svg += &format!("    <text x=\"{:.1}\" y=\"{}\">{}</text></g>\n", x(a.start) + 2.0, y + 15, task);
+    }
+    svg += &format!("  <text x=\"{}\" y=\"{}\">0</text>\n", LABEL, height - 2);
+    svg += &format!("  <text x=\"{:.1}\" y=\"{}\" text-anchor=\"end\">{}</text>\n", x(schedule.total_time), height - 2, schedule.total_time);
+    svg += "</svg>\n";
+    format!("<!DOCTYPE html>\n<html>\n<head><title>Step schedule</title></head>\n<body>\n{}</body>\n</html>\n", svg)
 }
 
 fn solve(input: &str) -> (String, usize) {
@@ -76,10 +324,60 @@ fn solve(input: &str) -> (String, usize) {
     (part1, part2)
 }
 
-pub fn run(input: &str) {
-    let (part1, part2) = solve(input);
-    println!("the solution to part 1 is {}", part1);
-    println!("the solution to part 2 is {}", part2);
+// Schedule a task list with any step names, taking durations from the
+// list or else from the puzzle's rule.
+fn run_schedule(input: &str, workers: usize) -> Result<Schedule<String>, String> {
+    let (graph, durations) = parse_tasks(input)?;
+    for task in graph.tasks.iter() {
+        if !durations.contains_key(task) && puzzle_time(task).is_none() {
+            return Err(format!("no duration given for step {}", task));
+        }
+    }
+    let time = |task: &String| durations.get(task).cloned().or_else(|| puzzle_time(task)).unwrap();
+    schedule(&graph, workers, time)
+        .map_err(|cycle| format!("steps {} depend on each other", cycle.join(", ")))
+}
+
+pub fn run(input: &str, options: &[String]) {
+    if let Err(err) = run_options(input, Options::new(options)) {
+        eprintln!("{}", err);
+    }
+}
+
+const NO_WORKERS: &str = "the number of workers must be at least 1";
+
+fn run_options(input: &str, options: Options) -> Result<(), String> {
+    match options.mode() {
+        Some("report") => {
+            let max_workers = options.parse_or(1, "number of workers", 10)?;
+            if max_workers == 0 {
+                return Err(NO_WORKERS.to_string());
+            }
+            let instrs = parse_input(input);
+            print!("{}", show_report(&report(&instrs, max_workers, |s| (s - b'A') as usize + 61)));
+        }
+        Some("gantt") => {
+            let workers = options.parse_or(1, "number of workers", 5)?;
+            if workers == 0 {
+                return Err(NO_WORKERS.to_string());
+            }
+            let schedule = run_schedule(input, workers)?;
+            let seconds_per_char = schedule.total_time.div_ceil(100);
+            print!("{}", gantt_text(&schedule, seconds_per_char.max(1)));
+            println!("all steps done after {} seconds", schedule.total_time);
+            if let Some(path) = options.get(2) {
+                fs::write(path, gantt_html(&schedule))
+                    .map_err(|err| format!("could not write {}: {}", path, err))?;
+            }
+        }
+        Some(_) => return Err(options.unknown()),
+        None => {
+            let (part1, part2) = solve(input);
+            println!("the solution to part 1 is {}", part1);
+            println!("the solution to part 2 is {}", part2);
+        }
+    }
+    Ok(())
 }
 
 #[cfg(test)]
@@ -118,6 +416,108 @@ Step F must be finished before step E can begin.
         assert_eq!(15, part2);
     }
 
+    #[test]
+    fn assignments() {
+        let deps = parse_input(EXAMPLE).into_iter()
+            .map(|(pre, dep)| (char::from(pre), char::from(dep)))
+            .collect::<Vec<_>>();
+        let graph = TaskGraph::from_deps(&deps);
+        let schedule = schedule(&graph, 2, |&s| (s as u8 - b'A') as usize + 1).unwrap();
+        let done = schedule.assignments.iter()
+            .map(|a| (a.task, a.worker, a.start, a.end))
+            .collect::<Vec<_>>();
+        assert_eq!(done, vec![
+            ('C', 0, 0, 3),
+            ('A', 0, 3, 4),
+            ('B', 0, 4, 6),
+            ('F', 1, 3, 9),
+            ('D', 0, 6, 10),
+            ('E', 0, 10, 15),
+        ]);
+        assert_eq!(gantt_text(&schedule, 1), "\
+worker 0 |C--AB-D---E----|
+worker 1 |...F-----......|
+");
+        assert_eq!(gantt_text(&schedule, 5), "\
+worker 0 |CDE|
+worker 1 |.F.|
+");
+        let html = gantt_html(&schedule);
+        assert_eq!(html.matches("<rect").count(), 6);
+        assert!(html.contains("<title>F (3 to 9)</title>"));
+    }
+
+    #[test]
+    fn critical_path() {
+        let instrs = parse_input(EXAMPLE);
+        let report = report(&instrs, 4, |s| (s - b'A') as usize + 1);
+        assert_eq!(report.critical_path, b"CFE");
+        assert_eq!(report.critical_time, 14);
+        assert_eq!(report.slack, vec![
+            (b'A', 3, 1),
+            (b'B', 4, 3),
+            (b'C', 0, 0),
+            (b'D', 4, 1),
+            (b'E', 9, 0),
+            (b'F', 3, 0),
+        ]);
+        assert_eq!(report.completion_times, vec![21, 15, 14, 14]);
+        assert!(show_report(&report).ends_with("more than 3 workers don't help\n"));
+    }
+
+    #[test]
+    fn named_tasks() {
+        let tasks = "\
+Step dig must be finished before step pour can begin.
+Step pour must be finished before step frame can begin.
+Step order must be finished before step frame can begin.
+Step dig takes 3 seconds.
+Step pour takes 2 seconds.
+Step order takes 4 seconds.
+Step frame takes 5 seconds.
+Step paint takes 1 second.
+";
+        let schedule = run_schedule(tasks, 2).unwrap();
+        assert_eq!(schedule.total_time, 11);
+        let order = schedule.assignments.iter().map(|a| a.task.as_str()).collect::<Vec<_>>();
+        assert_eq!(order, vec!["dig", "order", "paint", "pour", "frame"]);
+
+        let schedule = run_schedule("\
+Step R&D must be finished before step a<b can begin.
+Step R&D takes 2 seconds.
+Step a<b takes 1 second.
+", 1).unwrap();
+        let html = gantt_html(&schedule);
+        assert!(html.contains("<title>R&amp;D (0 to 2)</title>"));
+        assert!(html.contains(">a&lt;b</text>"));
+        assert!(!html.contains("R&D") && !html.contains("a<b"));
+
+        assert!(run_schedule("Step dig must be finished before step lunch can begin.\n", 1).is_err());
+        assert!(run_schedule("Step A must be finished before step lunch can begin.\n", 1).is_err());
+    }
+
+    #[test]
+    fn cycles() {
+        let graph = TaskGraph::from_deps(&parse_input("\
+Step A must be finished before step B can begin.
+Step B must be finished before step C can begin.
+Step C must be finished before step D can begin.
+Step D must be finished before step B can begin.
+Step D must be finished before step E can begin.
+"));
+        assert_eq!(schedule(&graph, 2, |_| 1), Err(vec![b'B', b'C', b'D']));
+        let graph = TaskGraph::from_deps(&[("x", "x")]);
+        assert_eq!(schedule(&graph, 1, |_| 1), Err(vec!["x"]));
+    }
+
+    #[test]
+    fn no_workers() {
+        for mode in &["report", "gantt"] {
+            let args = vec![mode.to_string(), "0".to_string()];
+            assert_eq!(run_options(EXAMPLE, Options::new(&args)), Err(NO_WORKERS.to_string()));
+        }
+    }
+
     #[cfg(feature="test_real_input")]
     #[test]
     fn real_input() {
@@ -1,58 +1,279 @@
+use std::fmt::Write;
+use std::fs;
+use std::ops::RangeInclusive;
+use crate::rng::Rng;
+use crate::options::Options;
+
 fn parse_input(s: &str) -> Vec<u32> {
     s.split_whitespace().map(|word| word.parse().unwrap()).collect()
 }
 
-#[derive(Clone, Debug)]
+type NodeId = usize;
+
+#[derive(Clone, Debug, PartialEq)]
 struct Node {
-    children: Vec<Node>,
+    children: Vec<NodeId>,
     metadata: Vec<u32>,
 }
 
-impl Node {
-    fn new() -> Self {
-        Node { children: vec![], metadata: vec![] }
-    }
+// All the nodes of a license tree, in the order their headers appear in
+// the input, so the root comes first and every node comes before its
+// children. Nothing here recurses, so trees of any depth are fine.
+#[derive(Clone, Debug, PartialEq)]
+struct Tree {
+    nodes: Vec<Node>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Visit {
+    Enter(NodeId),
+    Leave(NodeId),
+}
 
-    fn parse<I>(entries: &mut I) -> Self
-        where I: Iterator<Item = u32>
-    {
-        let child_count = entries.next().unwrap();
-        let metadata_count = entries.next().unwrap();
-        let mut node = Node::new();
-        for _ in 0..child_count {
-            node.children.push(Node::parse(entries));
+impl Tree {
+    fn parse(entries: &[u32]) -> Result<Tree, String> {
+        let mut entries = entries.iter().cloned();
+        let mut next = || entries.next().ok_or_else(|| "unexpected end of license".to_string());
+        let mut nodes: Vec<Node> = vec![];
+        // Nodes still being read, with the number of children and
+        // metadata entries still to come.
+        let mut open: Vec<(NodeId, u32, u32)> = vec![];
+        loop {
+            match open.last_mut() {
+                Some((_, children, _)) if *children > 0 => *children -= 1,
+                Some(&mut (id, 0, metadata_count)) => {
+                    for _ in 0..metadata_count {
+                        nodes[id].metadata.push(next()?);
+                    }
+                    open.pop();
+                    if open.is_empty() {
+                        break;
+                    }
+                    continue;
+                }
+                None if !nodes.is_empty() => break,
+                _ => {}
+            }
+            let child_count = next()?;
+            let metadata_count = next()?;
+            let id = nodes.len();
+            if let Some(&(parent, _, _)) = open.last() {
+                nodes[parent].children.push(id);
+            }
+            nodes.push(Node { children: vec![], metadata: vec![] });
+            open.push((id, child_count, metadata_count));
         }
-        for _ in 0..metadata_count {
-            node.metadata.push(entries.next().unwrap());
+        match entries.count() {
+            0 => Ok(Tree { nodes }),
+            n => Err(format!("{} entries left over after the license", n)),
         }
-        node
     }
 
     fn sum_metadata(&self) -> u32 {
-        self.children.iter().map(|c| c.sum_metadata()).sum::<u32>()
-            + self.metadata.iter().sum::<u32>()
+        self.nodes.iter().flat_map(|n| n.metadata.iter()).sum()
     }
 
+    // Children come after their parents, so working backwards every
+    // child's value is known before its parent's.
     fn value(&self) -> u32 {
-        if !self.children.is_empty() {
-            let subvals = self.children.iter().map(|c| c.value()).collect::<Vec<_>>();
-            self.metadata.iter().map(|&i| subvals.get(i as usize - 1).unwrap_or(&0)).sum()
-        } else {
-            self.metadata.iter().sum()
+        let mut values = vec![0; self.nodes.len()];
+        for (id, node) in self.nodes.iter().enumerate().rev() {
+            values[id] = if !node.children.is_empty() {
+                node.metadata.iter()
+                    .filter_map(|&i| node.children.get((i as usize).wrapping_sub(1)))
+                    .map(|&child| values[child])
+                    .sum()
+            } else {
+                node.metadata.iter().sum()
+            };
+        }
+        values[0]
+    }
+
+    // Depth-first walk, entering each node before its children and
+    // leaving it after them.
+    fn walk(&self) -> Vec<Visit> {
+        let mut visits = vec![];
+        let mut stack = vec![Visit::Enter(0)];
+        while let Some(visit) = stack.pop() {
+            visits.push(visit);
+            if let Visit::Enter(id) = visit {
+                stack.push(Visit::Leave(id));
+                stack.extend(self.nodes[id].children.iter().rev().map(|&c| Visit::Enter(c)));
+            }
+        }
+        visits
+    }
+
+    // The license numbers for the tree, the reverse of parse.
+    fn encode(&self) -> Vec<u32> {
+        let mut entries = vec![];
+        for visit in self.walk() {
+            match visit {
+                Visit::Enter(id) => {
+                    let node = &self.nodes[id];
+                    entries.push(node.children.len() as u32);
+                    entries.push(node.metadata.len() as u32);
+                }
+                Visit::Leave(id) => entries.extend(self.nodes[id].metadata.iter()),
+            }
+        }
+        entries
+    }
+
+    // Nested objects with the same fields as the browser viewer's nodes.
+    fn to_json(&self) -> String {
+        let mut json = String::new();
+        let mut last = None;
+        for visit in self.walk() {
+            match visit {
+                Visit::Enter(id) => {
+                    if let Some(Visit::Leave(_)) = last {
+                        json.push(',');
+                    }
+                    let metadata = self.nodes[id].metadata.iter().map(|m| m.to_string()).collect::<Vec<_>>();
+                    write!(json, "{{\"metadata\":[{}],\"subnodes\":[", metadata.join(",")).unwrap();
+                }
+                Visit::Leave(_) => json.push_str("]}"),
+            }
+            last = Some(visit);
         }
+        json
     }
+
+    fn to_dot(&self) -> String {
+        let mut dot = String::from("digraph license {\n");
+        for (id, node) in self.nodes.iter().enumerate() {
+            let metadata = node.metadata.iter().map(|m| m.to_string()).collect::<Vec<_>>();
+            writeln!(dot, "  n{} [label=\"{}\"];", id, metadata.join(" ")).unwrap();
+            for child in node.children.iter() {
+                writeln!(dot, "  n{} -> n{};", id, child).unwrap();
+            }
+        }
+        dot + "}\n"
+    }
+}
+
+// Random trees for stress testing. The number of children and metadata
+// entries of each node are drawn evenly from the given ranges, except
+// that nodes at the maximum depth have no children and the tree stops
+// growing at max_nodes. Metadata entries are from 1 to max_entry, which
+// must be at least 1.
+#[derive(Clone, Debug)]
+struct TreeParams {
+    max_depth: usize,
+    children: RangeInclusive<usize>,
+    metadata: RangeInclusive<usize>,
+    max_entry: u32,
+    max_nodes: usize,
+}
+
+impl Default for TreeParams {
+    fn default() -> TreeParams {
+        TreeParams { max_depth: 6, children: 0..=5, metadata: 1..=3, max_entry: 5, max_nodes: 2000 }
+    }
+}
+
+fn random_tree(params: &TreeParams, seed: u64) -> Tree {
+    assert!(params.max_entry >= 1, "max_entry must be at least 1");
+    let mut rng = Rng::new(seed);
+    let mut pick = |r: &RangeInclusive<usize>| r.start() + rng.below(r.end() - r.start() + 1);
+    let mut nodes: Vec<Node> = vec![];
+    // Nodes still to be made, as (parent, depth). Siblings are pushed in
+    // reverse so they come off in order and the nodes end up in the same
+    // order as parse would give.
+    let mut to_make: Vec<(Option<NodeId>, usize)> = vec![(None, 0)];
+    while let Some((parent, depth)) = to_make.pop() {
+        let id = nodes.len();
+        if let Some(parent) = parent {
+            nodes[parent].children.push(id);
+        }
+        let entries = 1..=params.max_entry as usize;
+        let metadata = (0..pick(&params.metadata)).map(|_| pick(&entries) as u32).collect();
+        nodes.push(Node { children: vec![], metadata });
+        if depth < params.max_depth {
+            let room = params.max_nodes.saturating_sub(nodes.len() + to_make.len());
+            let children = pick(&params.children).min(room);
+            to_make.extend((0..children).map(|_| (Some(id), depth + 1)));
+        }
+    }
+    Tree { nodes }
+}
+
+fn show_entries(entries: &[u32]) -> String {
+    entries.iter().map(|e| e.to_string()).collect::<Vec<_>>().join(" ")
+}
+
+// Data for visualizations/day08.html: the license numbers, as in
+// input_08.js, along with the parsed tree so the viewer doesn't have to
+// parse it again.
+fn to_js(tree: &Tree) -> String {
+    let license = show_entries(&tree.encode());
+    format!("const LICENSE = '{}';\nconst LICENSE_TREE = {};\n", license, tree.to_json())
+}
+
+fn export(input: &str, path: &str) -> Result<(), String> {
+    let entries = parse_input(input);
+    let tree = Tree::parse(&entries)?;
+    let text = if path.ends_with(".json") {
+        tree.to_json() + "\n"
+    } else if path.ends_with(".dot") {
+        tree.to_dot()
+    } else if path.ends_with(".js") {
+        to_js(&tree)
+    } else {
+        return Err(format!("unknown export format for {}", path));
+    };
+    fs::write(path, text).map_err(|err| err.to_string())
 }
 
 fn solve(input: &str) -> (u32, u32) {
     let input = parse_input(input);
-    let nodes = Node::parse(&mut input.iter().cloned());
-    (nodes.sum_metadata(), nodes.value())
+    let tree = Tree::parse(&input).unwrap();
+    (tree.sum_metadata(), tree.value())
+}
+
+// Parse a range like 2-5, or a single number.
+fn parse_range(s: &str) -> Option<RangeInclusive<usize>> {
+    let mut parts = s.splitn(2, '-').map(|n| n.parse().ok());
+    let lo = parts.next()??;
+    let hi = parts.next().unwrap_or(Some(lo))?;
+    if lo <= hi { Some(lo..=hi) } else { None }
+}
+
+pub fn run(input: &str, options: &[String]) {
+    if let Err(err) = run_options(input, Options::new(options)) {
+        eprintln!("{}", err);
+    }
 }
 
-pub fn run(input: &str) {
-    let (part1, part2) = solve(input);
-    println!("the solution to part 1 is {}", part1);
-    println!("the solution to part 2 is {}", part2);
+fn run_options(input: &str, options: Options) -> Result<(), String> {
+    match options.mode() {
+        Some("generate") => {
+            let range = |i: usize, default: RangeInclusive<usize>| match options.get(i) {
+                Some(s) => parse_range(s).ok_or_else(|| format!("invalid range: {}", s)),
+                None => Ok(default),
+            };
+            let mut params = TreeParams::default();
+            let seed = options.parse_or(1, "seed", 0)?;
+            params.max_depth = options.parse_or(2, "depth", params.max_depth)?;
+            params.children = range(3, params.children)?;
+            params.metadata = range(4, params.metadata)?;
+            params.max_nodes = options.parse_or(5, "number of nodes", params.max_nodes)?;
+            println!("{}", show_entries(&random_tree(&params, seed).encode()));
+        }
+        Some("export") => {
+            let path = options.required(1, "file name")?;
+            export(input, path).map_err(|err| format!("could not export to {}: {}", path, err))?;
+        }
+        Some(_) => return Err(options.unknown()),
+        None => {
+            let (part1, part2) = solve(input);
+            println!("the solution to part 1 is {}", part1);
+            println!("the solution to part 2 is {}", part2);
+        }
+    }
+    Ok(())
 }
 
 #[cfg(test)]
@@ -76,6 +297,93 @@ mod tests {
         assert_eq!(66, part2);
     }
 
+    #[test]
+    fn arena() {
+        let tree = Tree::parse(&parse_input(EXAMPLE)).unwrap();
+        assert_eq!(tree.nodes, vec![
+            Node { children: vec![1, 2], metadata: vec![1, 1, 2] },
+            Node { children: vec![], metadata: vec![10, 11, 12] },
+            Node { children: vec![3], metadata: vec![2] },
+            Node { children: vec![], metadata: vec![99] },
+        ]);
+        assert!(Tree::parse(&[2, 3, 0, 3, 10, 11]).is_err());
+        assert!(Tree::parse(&[0, 1, 5, 7]).is_err());
+        assert!(Tree::parse(&[]).is_err());
+    }
+
+    #[test]
+    fn deep_tree() {
+        // A chain of a million nodes, each pointing at its only child.
+        let depth = 1_000_000;
+        let mut entries = vec![];
+        for _ in 0..depth {
+            entries.extend(&[1, 1]);
+        }
+        entries.extend(&[0, 1, 5]);
+        entries.extend(vec![1; depth]);
+        let tree = Tree::parse(&entries).unwrap();
+        assert_eq!(tree.nodes.len(), depth + 1);
+        assert_eq!(tree.sum_metadata(), depth as u32 + 5);
+        assert_eq!(tree.value(), 5);
+        // Each node is {"metadata":[1],"subnodes":[...]}
+        assert_eq!(tree.to_json().len(), (depth + 1) * 30);
+    }
+
+    #[test]
+    fn exports() {
+        let entries = parse_input(EXAMPLE);
+        let tree = Tree::parse(&entries).unwrap();
+        assert_eq!(tree.to_json(), "\
+{\"metadata\":[1,1,2],\"subnodes\":[\
+{\"metadata\":[10,11,12],\"subnodes\":[]},\
+{\"metadata\":[2],\"subnodes\":[{\"metadata\":[99],\"subnodes\":[]}]}]}");
+        assert_eq!(tree.to_dot(), "\
+digraph license {
+  n0 [label=\"1 1 2\"];
+  n0 -> n1;
+  n0 -> n2;
+  n1 [label=\"10 11 12\"];
+  n2 [label=\"2\"];
+  n2 -> n3;
+  n3 [label=\"99\"];
+}
+");
+        assert!(to_js(&tree).starts_with("const LICENSE = '2 3 0 3 10 11 12 1 1 0 1 99 2 1 1 2';\n"));
+    }
+
+    #[test]
+    fn round_trip() {
+        let entries = parse_input(EXAMPLE);
+        assert_eq!(Tree::parse(&entries).unwrap().encode(), entries);
+
+        let shapes = [
+            TreeParams::default(),
+            TreeParams { max_depth: 0, ..TreeParams::default() },
+            TreeParams { children: 1..=1, metadata: 0..=2, ..TreeParams::default() },
+            TreeParams { max_depth: 3000, children: 0..=2, metadata: 0..=1, max_entry: 3, max_nodes: 5000 },
+            TreeParams { max_depth: 4, children: 5..=9, metadata: 2..=2, max_entry: 12, max_nodes: 200 },
+        ];
+        for params in shapes.iter() {
+            for seed in 0..20 {
+                let tree = random_tree(params, seed);
+                assert!(tree.nodes.len() <= params.max_nodes);
+                for node in tree.nodes.iter() {
+                    assert!(params.metadata.contains(&node.metadata.len()));
+                    assert!(node.metadata.iter().all(|&m| m >= 1 && m <= params.max_entry));
+                }
+                assert_eq!(Tree::parse(&tree.encode()), Ok(tree), "{:?} seed {}", params, seed);
+            }
+        }
+    }
+
+    #[test]
+    fn ranges() {
+        assert_eq!(parse_range("2-5"), Some(2..=5));
+        assert_eq!(parse_range("3"), Some(3..=3));
+        assert_eq!(parse_range("5-2"), None);
+        assert_eq!(parse_range("x"), None);
+    }
+
     #[cfg(feature="test_real_input")]
     #[test]
     fn real_input() {
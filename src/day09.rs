@@ -1,16 +1,27 @@
 use std::collections::VecDeque;
+use crate::options::Options;
 
 fn parse_input(s: &str) -> (usize, u32) {
     let words = s.split_whitespace().collect::<Vec<_>>();
     (words[0].parse().unwrap(), words[6].parse().unwrap())
 }
 
+// Marbles are placed clockwise of the current marble, and taken from
+// the current position.
+trait Circle {
+    fn with_capacity(marbles: usize) -> Self;
+    fn place(&mut self, m: u32);
+    fn take(&mut self) -> u32;
+    fn cw(&mut self);
+    fn ccw(&mut self);
+}
+
 // *Current marble* is in the back.
 struct MarbleCircle(VecDeque<u32>);
 
-impl MarbleCircle {
-    fn new() -> Self {
-        let mut deq = VecDeque::new();
+impl Circle for MarbleCircle {
+    fn with_capacity(marbles: usize) -> Self {
+        let mut deq = VecDeque::with_capacity(marbles);
         deq.push_back(0);
         MarbleCircle(deq)
     }
@@ -34,43 +45,196 @@ impl MarbleCircle {
     }
 }
 
-fn marble_game(num_players: usize, last_marble: u32) -> Vec<u32> {
-    let mut scores = vec![0; num_players];
+// Doubly linked ring stored in arrays indexed by marble number, so every
+// operation is O(1). Taking a marble makes its counter-clockwise neighbor
+// current, the same as the deque version.
+struct LinkedCircle {
+    next: Vec<u32>,
+    prev: Vec<u32>,
+    current: u32,
+}
+
+impl Circle for LinkedCircle {
+    fn with_capacity(marbles: usize) -> Self {
+        let mut next = Vec::with_capacity(marbles);
+        let mut prev = Vec::with_capacity(marbles);
+        next.push(0);
+        prev.push(0);
+        LinkedCircle { next, prev, current: 0 }
+    }
+
+    fn place(&mut self, m: u32) {
+        let (a, b) = (self.current, self.next[self.current as usize]);
+        if self.next.len() <= m as usize {
+            self.next.resize(m as usize + 1, 0);
+            self.prev.resize(m as usize + 1, 0);
+        }
+        self.next[m as usize] = b;
+        self.prev[m as usize] = a;
+        self.next[a as usize] = m;
+        self.prev[b as usize] = m;
+        self.current = m;
+    }
+
+    fn take(&mut self) -> u32 {
+        let m = self.current;
+        let (a, b) = (self.prev[m as usize], self.next[m as usize]);
+        self.next[a as usize] = b;
+        self.prev[b as usize] = a;
+        self.current = a;
+        m
+    }
+
+    fn cw(&mut self) {
+        self.current = self.next[self.current as usize];
+    }
+
+    fn ccw(&mut self) {
+        self.current = self.prev[self.current as usize];
+    }
+}
+
+// A player scoring a marble plus the marble it lets them take.
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct ScoringEvent {
+    player: usize,
+    marble: u32,
+    taken: u32,
+}
+
+// Every marble that is a multiple of modulus scores instead of being
+// placed: the marble rewind places counter-clockwise of the current one is
+// taken. Other marbles are placed offset places clockwise of the current
+// marble, between it and the next. Part 2 plays multiplier times as many
+// marbles.
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct GameRules {
+    modulus: u32,
+    rewind: usize,
+    offset: usize,
+    multiplier: u32,
+}
+
+impl Default for GameRules {
+    fn default() -> GameRules {
+        GameRules { modulus: 23, rewind: 7, offset: 1, multiplier: 100 }
+    }
+}
+
+impl GameRules {
+    // Parse `rules MODULUS REWIND OFFSET [MULTIPLIER]` from the command line.
+    fn parse(args: Options) -> Result<GameRules, String> {
+        let default = GameRules::default();
+        let rules = GameRules {
+            modulus: args.parse_at(1, "modulus")?,
+            rewind: args.parse_at(2, "rewind length")?,
+            offset: args.parse_at(3, "placement offset")?,
+            multiplier: args.parse_or(4, "multiplier", default.multiplier)?,
+        };
+        // A smaller modulus could take the last marble in the circle.
+        if rules.modulus < 2 {
+            return Err("the modulus must be at least 2".to_string());
+        }
+        Ok(rules)
+    }
+}
+
+fn play<C: Circle>(num_players: usize, last_marble: u32, rules: &GameRules) -> Vec<ScoringEvent> {
+    let mut events = vec![];
     let mut cur_player = 0;
-    let mut circle = MarbleCircle::new();
+    let mut circle = C::with_capacity(last_marble as usize + 1);
     for n in 1..=last_marble {
-        if n % 23 != 0 {
-            circle.cw();
+        if n % rules.modulus != 0 {
+            for _ in 0..rules.offset {
+                circle.cw();
+            }
             circle.place(n);
         } else {
-            scores[cur_player] += n;
-            for _ in 0..7 {
+            for _ in 0..rules.rewind {
                 circle.ccw();
             }
-            scores[cur_player] += circle.take();
+            events.push(ScoringEvent { player: cur_player, marble: n, taken: circle.take() });
             circle.cw();
         }
         cur_player = (cur_player + 1) % num_players;
     }
+    events
+}
+
+fn final_scores(num_players: usize, events: &[ScoringEvent]) -> Vec<u64> {
+    let mut scores = vec![0; num_players];
+    for e in events.iter() {
+        scores[e.player] += e.marble as u64 + e.taken as u64;
+    }
     scores
 }
 
-fn winning_score(n: usize, m: u32) -> u32 {
-    let scores = marble_game(n, m);
+// Each player's score over time, as (marble, score) pairs for every
+// marble where their score went up.
+fn score_history(num_players: usize, events: &[ScoringEvent]) -> Vec<Vec<(u32, u64)>> {
+    let mut history = vec![vec![]; num_players];
+    let mut scores = vec![0; num_players];
+    for e in events.iter() {
+        scores[e.player] += e.marble as u64 + e.taken as u64;
+        history[e.player].push((e.marble, scores[e.player]));
+    }
+    history
+}
+
+fn marble_game<C: Circle>(num_players: usize, last_marble: u32, rules: &GameRules) -> Vec<u64> {
+    final_scores(num_players, &play::<C>(num_players, last_marble, rules))
+}
+
+fn winning_score(n: usize, m: u32, rules: &GameRules) -> u64 {
+    let scores = marble_game::<LinkedCircle>(n, m, rules);
     *scores.iter().max().unwrap()
 }
 
-fn solve(input: &str) -> (u32, u32) {
+fn solve_with(input: &str, rules: &GameRules) -> Result<(u64, u64), String> {
     let (num_players, last_marble) = parse_input(input);
-    let winner1 = winning_score(num_players, last_marble);
-    let winner2 = winning_score(num_players, 100*last_marble);
-    (winner1, winner2)
+    let long_game = rules.multiplier.checked_mul(last_marble)
+        .ok_or_else(|| format!("{} times {} marbles is too many", rules.multiplier, last_marble))?;
+    // The deque is fast enough for the short game.
+    let winner1 = *marble_game::<MarbleCircle>(num_players, last_marble, rules).iter().max().unwrap();
+    let winner2 = winning_score(num_players, long_game, rules);
+    Ok((winner1, winner2))
 }
 
-pub fn run(input: &str) {
-    let (part1, part2) = solve(input);
+pub fn run(input: &str, options: &[String]) {
+    if let Err(err) = run_options(input, Options::new(options)) {
+        eprintln!("{}", err);
+    }
+}
+
+fn run_options(input: &str, options: Options) -> Result<(), String> {
+    let (history, options) = match options.mode() {
+        Some("history") => (true, options.rest()),
+        _ => (false, options),
+    };
+    let rules = match options.mode() {
+        Some("rules") => Some(GameRules::parse(options)?),
+        Some(_) => return Err(options.unknown()),
+        None => None,
+    };
+
+    if history {
+        // Print every scoring event as CSV, for graphing.
+        let (num_players, last_marble) = parse_input(input);
+        let events = play::<LinkedCircle>(num_players, last_marble, &rules.unwrap_or_default());
+        let history = score_history(num_players, &events);
+        let mut seen = vec![0; num_players];
+        println!("marble,player,taken,score");
+        for e in events.iter() {
+            println!("{},{},{},{}", e.marble, e.player, e.taken, history[e.player][seen[e.player]].1);
+            seen[e.player] += 1;
+        }
+        return Ok(());
+    }
+
+    let (part1, part2) = solve_with(input, &rules.unwrap_or_default())?;
     println!("the solution to part 1 is {}", part1);
     println!("the solution to part 2 is {}", part2);
+    Ok(())
 }
 
 #[cfg(test)]
@@ -79,6 +243,10 @@ mod tests {
 
     const EXAMPLE : &'static str = "9 players; last marble is worth 25 points\n";
 
+    fn solve(input: &str) -> (u64, u64) {
+        solve_with(input, &GameRules::default()).unwrap()
+    }
+
     #[test]
     fn parsing() {
         assert_eq!(parse_input(EXAMPLE), (9, 25));
@@ -93,11 +261,71 @@ mod tests {
 
     #[test]
     fn example2() {
-        assert_eq!(8317, winning_score(10, 1618));
-        assert_eq!(146373, winning_score(13, 7999));
-        assert_eq!(2764, winning_score(17, 1104));
-        assert_eq!(54718, winning_score(21, 6111));
-        assert_eq!(37305, winning_score(30, 5807));
+        let rules = GameRules::default();
+        assert_eq!(8317, winning_score(10, 1618, &rules));
+        assert_eq!(146373, winning_score(13, 7999, &rules));
+        assert_eq!(2764, winning_score(17, 1104, &rules));
+        assert_eq!(54718, winning_score(21, 6111, &rules));
+        assert_eq!(37305, winning_score(30, 5807, &rules));
+    }
+
+    #[test]
+    fn circles_agree() {
+        let rules = GameRules::default();
+        for &(players, last) in &[(9, 25), (10, 1618), (13, 7999), (30, 5807)] {
+            assert_eq!(play::<MarbleCircle>(players, last, &rules),
+                       play::<LinkedCircle>(players, last, &rules));
+        }
+    }
+
+    #[test]
+    fn rule_variants() {
+        for modulus in 2..30 {
+            for rewind in 0..12 {
+                for offset in 0..4 {
+                    let rules = GameRules { modulus, rewind, offset, multiplier: 1 };
+                    assert_eq!(play::<MarbleCircle>(7, 500, &rules),
+                               play::<LinkedCircle>(7, 500, &rules),
+                               "{:?}", rules);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn rules_parsing() {
+        let parse = |s: &str| {
+            let args = s.split_whitespace().map(String::from).collect::<Vec<_>>();
+            GameRules::parse(Options::new(&args))
+        };
+        assert_eq!(parse("rules 23 7 1"), Ok(GameRules::default()));
+        assert_eq!(parse("rules 5 2 0 10"),
+                   Ok(GameRules { modulus: 5, rewind: 2, offset: 0, multiplier: 10 }));
+        assert_eq!(parse("rules 23 7"), Err("missing placement offset after rules".to_string()));
+        assert!(parse("rules 1 7 1").is_err());
+        assert_eq!(parse("rules 23 x 1"), Err("invalid rewind length: x".to_string()));
+        assert_eq!(parse("rules 23 7 1 4294967296"), Err("invalid multiplier: 4294967296".to_string()));
+        let rules = parse("rules 23 7 1 200000000").unwrap();
+        assert_eq!(solve_with(EXAMPLE, &rules), Err("200000000 times 25 marbles is too many".to_string()));
+
+        // With a rewind of 0 every scoring marble takes the marble placed
+        // just before it.
+        let rules = GameRules { modulus: 2, rewind: 0, offset: 1, multiplier: 1 };
+        assert_eq!(marble_game::<LinkedCircle>(2, 6, &rules), vec![0, 2 + 1 + 4 + 3 + 6 + 5]);
+    }
+
+    #[test]
+    fn history() {
+        let rules = GameRules::default();
+        let events = play::<LinkedCircle>(9, 25, &rules);
+        assert_eq!(events, vec![ScoringEvent { player: 4, marble: 23, taken: 9 }]);
+        let events = play::<LinkedCircle>(10, 1618, &rules);
+        let history = score_history(10, &events);
+        let scores = final_scores(10, &events);
+        for (player, h) in history.iter().enumerate() {
+            assert_eq!(h.last().map_or(0, |&(_, score)| score), scores[player]);
+            assert!(h.windows(2).all(|w| w[0].0 < w[1].0 && w[0].1 < w[1].1));
+        }
     }
 
     #[cfg(feature="test_real_input")]
@@ -1,20 +1,38 @@
-#[derive(Clone, Copy, Debug, PartialEq)]
+use std::collections::HashSet;
+use std::fs;
+use std::io;
+use std::iter;
+use std::ops::RangeInclusive;
+use crate::options::Options;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
 struct Point(i32, i32);
 
 #[derive(Clone, Copy, Debug, PartialEq)]
 struct Star(Point, Point);
 
 impl Star {
-    fn step(&mut self) {
-        self.jump(1);
-    }
-    
-    fn jump(&mut self, t: i32) {
-        (self.0).0 += (self.1).0 * t;
-        (self.0).1 += (self.1).1 * t;
+    fn at(&self, t: i32) -> Point {
+        Point((self.0).0 + (self.1).0 * t, (self.0).1 + (self.1).1 * t)
     }
 }
 
+// The letters of the 6x10 font that messages are written in, drawn the
+// way they appear in a message: 2 blank columns between letters.
+const FONT_LETTERS: &str = "ABCEFGHJKLNPRXZ";
+const FONT: [&str; 10] = [
+    "..##..  #####.  .####.  ######  ######  .####.  #....#  ...###  #....#  #.....  #....#  #####.  #####.  #....#  ######",
+    ".#..#.  #....#  #....#  #.....  #.....  #....#  #....#  ....#.  #...#.  #.....  ##...#  #....#  #....#  #....#  .....#",
+    "#....#  #....#  #.....  #.....  #.....  #.....  #....#  ....#.  #..#..  #.....  ##...#  #....#  #....#  .#..#.  .....#",
+    "#....#  #....#  #.....  #.....  #.....  #.....  #....#  ....#.  #.#...  #.....  #.#..#  #....#  #....#  .#..#.  ....#.",
+    "#....#  #####.  #.....  #####.  #####.  #.....  ######  ....#.  ##....  #.....  #.#..#  #####.  #####.  ..##..  ...#..",
+    "######  #....#  #.....  #.....  #.....  #..###  #....#  ....#.  ##....  #.....  #..#.#  #.....  #..#..  ..##..  ..#...",
+    "#....#  #....#  #.....  #.....  #.....  #....#  #....#  ....#.  #.#...  #.....  #..#.#  #.....  #...#.  .#..#.  .#....",
+    "#....#  #....#  #.....  #.....  #.....  #....#  #....#  #...#.  #..#..  #.....  #...##  #.....  #...#.  .#..#.  #.....",
+    "#....#  #....#  #....#  #.....  #.....  #...##  #....#  #...#.  #...#.  #.....  #...##  #.....  #....#  #....#  #.....",
+    "#....#  #####.  .####.  ######  #.....  .###.#  #....#  .###..  #....#  ######  #....#  #.....  #....#  #....#  ######",
+];
+
 fn parse_input(s: &str) -> Vec<Star> {
     s.lines().map(|line| {
         let mut parts = line.split(|c| c == '<' || c == '>' || c == ',');
@@ -28,59 +46,171 @@ fn parse_input(s: &str) -> Vec<Star> {
     }).collect()
 }
 
-fn show_stars(stars: &[Star], left: i32, right: i32, top: i32, bottom: i32) {
-    for y in top..=bottom {
-        for x in left..=right {
-            if stars.iter().any(|s| s.0 == Point(x,y)) {
-                print!("#");
-            } else {
-                print!(".");
-            }
+fn positions(stars: &[Star], t: i32) -> Vec<Point> {
+    stars.iter().map(|s| s.at(t)).collect()
+}
+
+// Left, right, top and bottom.
+fn bounds(points: &[Point]) -> (i32, i32, i32, i32) {
+    let min_x = points.iter().map(|p| p.0).min().unwrap();
+    let max_x = points.iter().map(|p| p.0).max().unwrap();
+    let min_y = points.iter().map(|p| p.1).min().unwrap();
+    let max_y = points.iter().map(|p| p.1).max().unwrap();
+    (min_x, max_x, min_y, max_y)
+}
+
+fn extent(stars: &[Star], t: i32) -> (i64, i64) {
+    let (left, right, top, bottom) = bounds(&positions(stars, t));
+    ((right - left) as i64, (bottom - top) as i64)
+}
+
+fn area(stars: &[Star], t: i32) -> i64 {
+    let (width, height) = extent(stars, t);
+    width * height
+}
+
+// Find the time when the stars' bounding box is smallest. The width of
+// the box is the largest of some linear functions of time less the
+// smallest, so it is convex, and so is the height and their sum. Search
+// for the first time that sum stops shrinking, then settle on the nearby
+// time of least area.
+fn converge_time(stars: &[Star]) -> i32 {
+    let spread = |t| {
+        let (width, height) = extent(stars, t);
+        width + height
+    };
+    let shrinking = |t| spread(t + 1) < spread(t);
+    let mut hi = 1;
+    while shrinking(hi) {
+        hi *= 2;
+    }
+    let mut lo = 0;
+    while lo < hi {
+        let mid = (lo + hi) / 2;
+        if shrinking(mid) {
+            lo = mid + 1;
+        } else {
+            hi = mid;
         }
-        println!();
     }
+
+    let mut t = lo;
+    while area(stars, t + 1) < area(stars, t) {
+        t += 1;
+    }
+    while t > 0 && area(stars, t - 1) < area(stars, t) {
+        t -= 1;
+    }
+    t
 }
 
-fn solve(input: &str) -> usize {
-    let mut stars = parse_input(input);
-    let mut last_area = usize::max_value();
-    let mut stop_time = 0;
-    for t in 0.. {
-        let min_x = stars.iter().map(|s| (s.0).0).min().unwrap();
-        let max_x = stars.iter().map(|s| (s.0).0).max().unwrap();
-        let min_y = stars.iter().map(|s| (s.0).1).min().unwrap();
-        let max_y = stars.iter().map(|s| (s.0).1).max().unwrap();
-
-        let width = (max_x - min_x) as usize;
-        let height = (max_y - min_y) as usize;
-        let area = width * height;
-        // println!("bounding rect {}x{} ({} total)", width, height, area);
-        if area > last_area {
-            stop_time = t - 1;
-            break;
+// Read the letters spelled out by the stars, or None unless they are all
+// letters of the font. Letters are told apart by the empty columns
+// between them.
+fn read_message(points: &[Point]) -> Option<String> {
+    let (left, right, top, bottom) = bounds(points);
+    if bottom - top + 1 != FONT.len() as i32 {
+        return None;
+    }
+    let lit = points.iter().cloned().collect::<HashSet<_>>();
+    let empty = |x| (top..=bottom).all(|y| !lit.contains(&Point(x, y)));
+    let mut message = String::new();
+    let mut x = left;
+    while x <= right {
+        let x0 = x;
+        while x <= right && !empty(x) {
+            x += 1;
         }
-        last_area = area;
-        stars.iter_mut().for_each(|s| s.step());
-    }
-
-    stars.iter_mut().for_each(|s| s.jump(-1));
-    let min_x = stars.iter().map(|s| (s.0).0).min().unwrap();
-    let max_x = stars.iter().map(|s| (s.0).0).max().unwrap();
-    let min_y = stars.iter().map(|s| (s.0).1).min().unwrap();
-    let max_y = stars.iter().map(|s| (s.0).1).max().unwrap();
-    show_stars(&stars, min_x-1, max_x+1, min_y-1, max_y+1);
-    
-    stop_time
+        let glyph = (top..=bottom).map(|y|
+            (x0..x).map(|x|
+                if lit.contains(&Point(x, y)) { '#' } else { '.' }
+            ).collect::<String>()
+        ).collect::<Vec<_>>();
+        let (_, c) = FONT_LETTERS.chars().enumerate()
+            .find(|&(i, _)| glyph.iter().zip(FONT.iter()).all(|(g, f)| g == &f[8*i..8*i+6]))?;
+        message.push(c);
+        while x <= right && empty(x) {
+            x += 1;
+        }
+    }
+    Some(message)
 }
 
-pub fn run(input: &str) {
-    let time = solve(input);
+fn render(points: &[Point]) -> String {
+    let (left, right, top, bottom) = bounds(points);
+    let lit = points.iter().cloned().collect::<HashSet<_>>();
+    (top..=bottom).map(|y|
+        (left..=right).map(|x| if lit.contains(&Point(x, y)) { '#' } else { '.' })
+            .chain(iter::once('\n'))
+            .collect::<String>()
+    ).collect()
+}
+
+// The stars at each time in a range, one frame per line: the time, then
+// x,y for each star.
+fn frames(stars: &[Star], times: RangeInclusive<i32>) -> String {
+    times.map(|t| {
+        let mut line = t.to_string();
+        for p in positions(stars, t) {
+            line += &format!(" {},{}", p.0, p.1);
+        }
+        line + "\n"
+    }).collect()
+}
+
+// Write the frames around the convergence time for the star field
+// viewer, either as a JS data file (like visualizations/input_05.js) or,
+// for any other file name, as plain text that the viewer can load.
+fn export(input: &str, path: &str, window: i32) -> io::Result<()> {
+    let stars = parse_input(input);
+    let time = converge_time(&stars);
+    let text = frames(&stars, (time - window).max(0)..=time + window);
+    if path.ends_with(".js") {
+        fs::write(path, format!("const STARS = '{}';\n", text.replace('\n', "\\n")))
+    } else {
+        fs::write(path, text)
+    }
+}
+
+fn solve(input: &str) -> (Option<String>, i32) {
+    let stars = parse_input(input);
+    let time = converge_time(&stars);
+    (read_message(&positions(&stars, time)), time)
+}
+
+pub fn run(input: &str, options: &[String]) {
+    if let Err(err) = run_options(input, Options::new(options)) {
+        eprintln!("{}", err);
+    }
+}
+
+fn run_options(input: &str, options: Options) -> Result<(), String> {
+    match options.mode() {
+        Some("export") => {
+            let path = options.required(1, "file name")?;
+            let window = options.parse_or(2, "frame window", 10)?;
+            return export(input, path, window).map_err(|err| format!("could not write {}: {}", path, err));
+        }
+        Some(_) => return Err(options.unknown()),
+        None => (),
+    }
+
+    let (message, time) = solve(input);
+    match message {
+        Some(message) => println!("the solution to part 1 is {}", message),
+        None => {
+            println!("the message for part 1 could not be read:");
+            print!("{}", render(&positions(&parse_input(input), time)));
+        }
+    }
     println!("the solution to part 2 is {}", time);
+    Ok(())
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::rng::Rng;
 
     const EXAMPLE : &'static str = "\
 position=< 9,  1> velocity=< 0,  2>
@@ -128,16 +258,80 @@ position=<-3,  6> velocity=< 2, -1>
 
     #[test]
     fn example() {
-        let part2 = solve(EXAMPLE);
+        let (part1, part2) = solve(EXAMPLE);
+        // The example is not written in the 6x10 font.
+        assert_eq!(None, part1);
         assert_eq!(3, part2);
     }
 
+    #[test]
+    fn rendering() {
+        let stars = parse_input(EXAMPLE);
+        assert_eq!(render(&positions(&stars, 3)), "\
+#...#..###
+#...#...#.
+#...#...#.
+#####...#.
+#...#...#.
+#...#...#.
+#...#...#.
+#...#..###
+");
+    }
+
+    #[test]
+    fn frame_export() {
+        let stars = parse_input(EXAMPLE);
+        let text = frames(&stars, 2..=4);
+        assert_eq!(text.lines().count(), 3);
+        for (line, t) in text.lines().zip(2..) {
+            let mut fields = line.split(' ');
+            assert_eq!(fields.next(), Some(t.to_string().as_str()));
+            let points = fields.map(|xy| {
+                let mut xy = xy.split(',').map(|n| n.parse().unwrap());
+                Point(xy.next().unwrap(), xy.next().unwrap())
+            }).collect::<Vec<_>>();
+            assert_eq!(points, positions(&stars, t));
+        }
+    }
+
+    // Lay out a message in the font, with letters pitch columns apart,
+    // and scatter the stars so that they come together at the given time.
+    fn scatter(message: &str, pitch: i32, time: i32, seed: u64) -> String {
+        let mut rng = Rng::new(seed);
+        let mut lines = String::new();
+        for (k, c) in message.chars().enumerate() {
+            let i = FONT_LETTERS.find(c).unwrap();
+            for (y, row) in FONT.iter().enumerate() {
+                for (x, _) in row[8*i..8*i+6].char_indices().filter(|&(_, c)| c == '#') {
+                    let (vx, vy) = (rng.below(11) as i32 - 5, rng.below(11) as i32 - 5);
+                    let (x, y) = (pitch*k as i32 + x as i32 - vx*time, y as i32 - vy*time);
+                    lines += &format!("position=<{}, {}> velocity=<{}, {}>\n", x, y, vx, vy);
+                }
+            }
+        }
+        lines
+    }
+
+    #[test]
+    fn ocr() {
+        assert_eq!(solve(&scatter(FONT_LETTERS, 8, 10_345, 1)), (Some(FONT_LETTERS.to_string()), 10_345));
+        assert_eq!(solve(&scatter("ZEBRA", 8, 7, 2)), (Some("ZEBRA".to_string()), 7));
+        assert_eq!(solve(&scatter("X", 8, 0, 3)), (Some("X".to_string()), 0));
+        assert_eq!(solve(&scatter("HAL", 7, 50, 4)), (Some("HAL".to_string()), 50));
+        assert_eq!(solve(&scatter("ZAP", 13, 50, 5)), (Some("ZAP".to_string()), 50));
+        // Letters that touch can't be told apart.
+        assert_eq!(solve(&scatter("HH", 6, 50, 6)).0, None);
+    }
+
     #[cfg(feature="test_real_input")]
     #[test]
     fn real_input() {
         let input = include_str!("../inputs/day10.txt");
-        let x = solve(&input);
+        // The expected output only has the time; the message is read
+        // off the rendered stars.
+        let (_, time) = solve(&input);
         assert_eq!(include_str!("../outputs/day10.txt"),
-                   format!("{:?}", x));
+                   format!("{:?}", time));
     }
 }
@@ -1,3 +1,11 @@
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+use std::iter::StepBy;
+use std::ops::{Range, RangeInclusive};
+use std::thread;
+use crate::summed_area::SummedArea;
+use crate::options::{self, Options};
+
 fn parse_input(s: &str) -> i32 {
     s.trim().parse().unwrap()
 }
@@ -7,63 +15,207 @@ fn fuel_cell(x: i32, y: i32, ser_no: i32) -> i32 {
     (((rack_id * y + ser_no) * rack_id) / 100) % 10 - 5
 }
 
-fn generate_grid(ser_no: i32) -> Vec<Vec<i32>> {
-    (1..=300).map(|y|
-        (1..=300).map(|x|
+const GRID_SIZE: usize = 300;
+
+fn generate_grid(ser_no: i32, size: usize) -> Vec<Vec<i32>> {
+    (1..=size as i32).map(|y|
+        (1..=size as i32).map(|x|
             fuel_cell(x, y, ser_no)
         ).collect()
     ).collect()
 }
 
-fn partial_sums(grid: &[Vec<i32>]) -> Vec<Vec<i32>> {
-    let mut p = vec![vec![0; 301]; 301];
-    for y in 1..=300 {
-        for x in 1..=300 {
-            p[y][x] = grid[y-1][x-1] + p[y-1][x] + p[y][x-1] - p[y-1][x-1];
+// The sum of the size x size square with its top-left corner at (x, y),
+// counting from 0.
+fn square_sum(sums: &SummedArea<i32>, x: usize, y: usize, size: usize) -> i32 {
+    sums.sum(y..y+size, x..x+size)
+}
+
+// A square of fuel cells and its total power. The coordinates of its
+// top-left cell count from 1, as in the puzzle.
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct Square {
+    x: usize,
+    y: usize,
+    size: usize,
+    power: i32,
+}
+
+type RankKey = (Reverse<i32>, usize, usize, usize);
+
+impl Square {
+    // Most power first, ties broken in reading order and then by size.
+    fn rank(&self) -> RankKey {
+        (Reverse(self.power), self.y, self.x, self.size)
+    }
+}
+
+// The best k squares offered so far. The heap's top is the worst of them.
+struct Ranking {
+    k: usize,
+    heap: BinaryHeap<RankKey>,
+}
+
+impl Ranking {
+    fn new(k: usize) -> Ranking {
+        Ranking { k, heap: BinaryHeap::with_capacity(k + 1) }
+    }
+
+    fn offer(&mut self, square: Square) {
+        self.heap.push(square.rank());
+        if self.heap.len() > self.k {
+            self.heap.pop();
+        }
+    }
+
+    // The power a square needs to have a chance of making the ranking.
+    fn threshold(&self) -> Option<i32> {
+        if self.heap.len() < self.k {
+            None
+        } else {
+            self.heap.peek().map(|&(Reverse(power), ..)| power)
+        }
+    }
+
+    fn into_squares(self) -> Vec<Square> {
+        self.heap.into_iter()
+            .map(|(Reverse(power), y, x, size)| Square { x, y, size, power })
+            .collect()
+    }
+}
+
+struct PowerGrid {
+    height: usize,
+    width: usize,
+    sums: SummedArea<i32>,
+    // Sums of only the cells with positive power.
+    positive: SummedArea<i32>,
+}
+
+impl PowerGrid {
+    fn new(grid: &[Vec<i32>]) -> PowerGrid {
+        let height = grid.len();
+        let width = grid.first().map_or(0, |row| row.len());
+        PowerGrid {
+            height,
+            width,
+            sums: SummedArea::new(grid),
+            positive: SummedArea::from_fn(height, width, |y, x| grid[y][x].max(0)),
+        }
+    }
+
+    // Rank the squares with their corners in the given rows. Squares are
+    // tried from each corner in increasing size. Growing a square from
+    // size s to any larger size can add at most the positive cells
+    // between it and the largest square at that corner, so once that
+    // can't lift it to the threshold, the larger squares are skipped.
+    fn search(&self, rows: StepBy<Range<usize>>, sizes: &RangeInclusive<usize>, k: usize) -> Ranking {
+        let (height, width) = (self.height, self.width);
+        let mut ranking = Ranking::new(k);
+        for y in rows {
+            for x in 0..width {
+                let largest = (*sizes.end()).min(height - y).min(width - x);
+                let best_gain = |size| {
+                    square_sum(&self.positive, x, y, largest) - square_sum(&self.positive, x, y, size)
+                };
+                for size in *sizes.start()..=largest {
+                    let power = square_sum(&self.sums, x, y, size);
+                    match ranking.threshold() {
+                        Some(threshold) if power < threshold => {
+                            if power + best_gain(size) < threshold {
+                                break;
+                            }
+                        }
+                        _ => ranking.offer(Square { x: x+1, y: y+1, size, power }),
+                    }
+                }
+            }
         }
+        ranking
     }
-    p
 }
 
-fn find_most_power(psum_grid: &[Vec<i32>], window: usize) -> (i32, (usize, usize)) {
-    (0..=300-window).flat_map(|y|
-        (0..=(300-window)).map(move |x| {
-            let a = psum_grid[y+window][x+window];
-            let b = psum_grid[y+window][x];
-            let c = psum_grid[y][x+window];
-            let d = psum_grid[y][x];
-            (a - b - c + d, (x+1, y+1))
-        }))
-        .max_by_key(|&(power,_)| power).unwrap()
+// The k most powerful squares with sizes in the given range, best first,
+// optionally dividing the rows among several threads.
+fn top_squares(grid: &[Vec<i32>], sizes: RangeInclusive<usize>, k: usize, threads: usize) -> Vec<Square> {
+    let power_grid = PowerGrid::new(grid);
+    let rows = |first: usize, step: usize| (first..grid.len()).step_by(step);
+    let mut squares = if threads <= 1 {
+        power_grid.search(rows(0, 1), &sizes, k).into_squares()
+    } else {
+        thread::scope(|scope| {
+            let handles = (0..threads).map(|first| {
+                let (power_grid, sizes) = (&power_grid, &sizes);
+                scope.spawn(move || power_grid.search(rows(first, threads), sizes, k))
+            }).collect::<Vec<_>>();
+            handles.into_iter()
+                .flat_map(|handle| handle.join().unwrap().into_squares())
+                .collect::<Vec<_>>()
+        })
+    };
+    squares.sort_by_key(Square::rank);
+    squares.truncate(k);
+    squares
 }
 
-fn solve(input: &str) -> (String, String) {
+fn solve_with(input: &str, size: usize, threads: usize) -> (String, String) {
     let grid_serial_no = parse_input(input);
-    let grid = generate_grid(grid_serial_no);
-    let psum_grid = partial_sums(&grid);
+    let grid = generate_grid(grid_serial_no, size);
 
-    let (_, best3) = find_most_power(&psum_grid, 3);
-    let part1 = format!("{},{}", best3.0, best3.1);
-    
-    let (_, best_pos, best_window) = (1..300).map(|window| {
-            let (power, pos) = find_most_power(&psum_grid, window);
-            (power, pos, window)
-        }).max_by_key(|&(power,_,_)| power).unwrap();
-    let part2 = format!("{},{},{}", best_pos.0, best_pos.1, best_window);
+    let best3 = top_squares(&grid, 3..=3, 1, threads)[0];
+    let part1 = format!("{},{}", best3.x, best3.y);
+
+    let best = top_squares(&grid, 1..=size, 1, threads)[0];
+    let part2 = format!("{},{},{}", best.x, best.y, best.size);
 
     (part1, part2)
 }
 
-pub fn run(input: &str) {
-    let (part1, part2) = solve(input);
+pub fn run(input: &str, options: &[String]) {
+    if let Err(err) = run_options(input, Options::new(options)) {
+        eprintln!("{}", err);
+    }
+}
+
+fn run_options(input: &str, options: Options) -> Result<(), String> {
+    let mut size = GRID_SIZE;
+    let mut threads = 1;
+    let mut top = None;
+    for (name, value) in options.pairs()? {
+        match name {
+            "size" => size = options::parse(value, "grid size")?,
+            "threads" => threads = options::parse(value, "number of threads")?,
+            "top" => top = Some(options::parse(value, "number of squares")?),
+            _ => return Err(format!("unknown option {}", name)),
+        }
+    }
+
+    if let Some(k) = top {
+        let grid = generate_grid(parse_input(input), size);
+        for (i, square) in top_squares(&grid, 1..=size, k, threads).iter().enumerate() {
+            println!("{:3}. {},{},{} with power {}", i + 1, square.x, square.y, square.size, square.power);
+        }
+        return Ok(());
+    }
+
+    // Part 1 looks at 3x3 squares.
+    if size < 3 {
+        return Err(format!("the grid size must be at least 3, not {}", size));
+    }
+    let (part1, part2) = solve_with(input, size, threads);
     println!("the solution to part 1 is {}", part1);
     println!("the solution to part 2 is {}", part2);
+    Ok(())
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    fn solve(input: &str) -> (String, String) {
+        solve_with(input, GRID_SIZE, 1)
+    }
+
     #[test]
     fn parsing() {
         assert_eq!(parse_input("18\n"), 18);
@@ -91,6 +243,50 @@ mod tests {
         assert_eq!(fuel_cell(101, 153, 71), 4);
     }
 
+    // Rank every square the slow way.
+    fn all_squares(grid: &[Vec<i32>]) -> Vec<Square> {
+        let n = grid.len();
+        let mut squares = vec![];
+        for size in 1..=n {
+            for y in 0..=n-size {
+                for x in 0..=n-size {
+                    let power = grid[y..y+size].iter().map(|row| row[x..x+size].iter().sum::<i32>()).sum();
+                    squares.push(Square { x: x+1, y: y+1, size, power });
+                }
+            }
+        }
+        squares.sort_by_key(Square::rank);
+        squares
+    }
+
+    #[test]
+    fn ranking() {
+        for &(ser_no, size) in &[(18, 20), (42, 25), (7, 1), (5000, 12)] {
+            let grid = generate_grid(ser_no, size);
+            let all = all_squares(&grid);
+            for k in [1, 5, 50, all.len()].iter().map(|&k| k.min(all.len())) {
+                assert_eq!(top_squares(&grid, 1..=size, k, 1), &all[..k]);
+                assert_eq!(top_squares(&grid, 1..=size, k, 3), &all[..k]);
+            }
+        }
+    }
+
+    #[test]
+    fn threads() {
+        assert_eq!(solve_with("18", 60, 4), solve_with("18", 60, 1));
+        let grid = generate_grid(42, 60);
+        assert_eq!(top_squares(&grid, 1..=60, 10, 4), top_squares(&grid, 1..=60, 10, 1));
+    }
+
+    #[test]
+    fn small_grids() {
+        let args = |s: &str| s.split_whitespace().map(String::from).collect::<Vec<_>>();
+        assert_eq!(run_options("18", Options::new(&args("size 2"))),
+                   Err("the grid size must be at least 3, not 2".to_string()));
+        assert_eq!(solve_with("18", 3, 1).0, "1,1");
+        assert_eq!(top_squares(&generate_grid(18, 2), 1..=2, 10, 1).len(), 5);
+    }
+
     #[cfg(feature="test_real_input")]
     #[test]
     fn real_input() {
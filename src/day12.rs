@@ -1,25 +1,83 @@
 use std::collections::HashMap;
+use crate::cycle::{self, Repeat};
 
-fn parse_input(s: &str) -> (&[u8], HashMap<&[u8],u8>) {
+// The widest rules, for which the number of possible patterns still fits
+// in a usize.
+const MAX_WIDTH: usize = usize::BITS as usize - 1;
+
+// Rules map each window of pots (any odd width, centered on the pot
+// being decided) to the next state of the pot. Patterns with no rule
+// lead to the default, which is no plant unless the rule list has a line
+// like "default => #".
+struct Rules<'a> {
+    width: usize,
+    table: HashMap<&'a [u8], u8>,
+    default: u8,
+}
+
+impl<'a> Rules<'a> {
+    fn get(&self, pattern: &[u8]) -> Option<u8> {
+        self.table.get(pattern).copied()
+    }
+
+    fn outcome(&self, pattern: &[u8]) -> u8 {
+        self.get(pattern).unwrap_or(self.default)
+    }
+
+    // Number of pots on either side of the center of a window.
+    fn reach(&self) -> usize {
+        self.width / 2
+    }
+
+    // Look for rule sets that the plant machine can't handle.
+    fn check(&self) -> Vec<String> {
+        let mut warnings = vec![];
+        let empty = vec![b'.'; self.width];
+        if is_plant(self.outcome(&empty)) {
+            warnings.push(format!("{} leads to a plant, so the row fills up forever",
+                String::from_utf8(empty).unwrap()));
+        }
+        let patterns = 1usize << self.width;
+        let missing = patterns - self.table.len();
+        if missing > 0 {
+            warnings.push(format!("{} of {} patterns have no rule and lead to {}",
+                missing, patterns, char::from(self.default)));
+        }
+        warnings
+    }
+}
+
+fn parse_input(s: &str) -> (&[u8], Rules<'_>) {
     let mut lines = s.lines();
     let init = lines.next().unwrap();
     let init = &init.as_bytes()[15..];
 
     lines.next(); // blank line
-    let rules = lines.map(|line| {
+    let mut default = b'.';
+    let table = lines.filter_map(|line| {
         let parts = line.split_whitespace().collect::<Vec<_>>();
         let rule = parts[0].as_bytes();
         let result = parts[2].as_bytes()[0];
-        (rule, result)
+        if parts[0] == "default" {
+            default = result;
+            None
+        } else {
+            Some((rule, result))
+        }
     }).collect::<HashMap<_,_>>();
 
-    (init, rules)
+    let width = table.keys().next().map_or(5, |rule| rule.len());
+    assert!(width % 2 == 1, "rules must have an odd width");
+    assert!(width <= MAX_WIDTH, "rules can be at most {} pots wide", MAX_WIDTH);
+    assert!(table.keys().all(|rule| rule.len() == width), "rules must all have the same width");
+    (init, Rules { width, table, default })
 }
 
-fn step(state: &[u8], rules: &HashMap<&[u8],u8>) -> Vec<u8> {
-    let temp = [b"....", state, b"...."].concat();
-    temp.windows(5)
-        .map(|w| if let Some(&x) = rules.get(w) { x } else { b'.' })
+fn step(state: &[u8], rules: &Rules) -> Vec<u8> {
+    let pad = vec![b'.'; rules.width - 1];
+    let temp = [&pad, state, &pad].concat();
+    temp.windows(rules.width)
+        .map(|w| rules.outcome(w))
         .collect::<Vec<_>>()
 }
 
@@ -31,17 +89,60 @@ fn is_plant(b: u8) -> bool {
     // state.iter().map(|&b| if is_plant(b) { '#' } else { '.' }).collect()
 // }
 
-fn plant_machine(initial_state: &[u8], rules: &HashMap<&[u8],u8>, gens: usize) -> (Vec<u8>, i64) {
-    let mut state = initial_state.to_vec();
-    let mut pos: i64 = 0;
+// A row of pots trimmed to run from its first plant to its last, along
+// with the number of the first pot. A row with no plants is empty.
+type Row = (Vec<u8>, i64);
+
+fn trim(state: &[u8], pos: i64) -> Row {
+    match state.iter().position(|&p| is_plant(p)) {
+        Some(first_plant) => {
+            let last_plant = state.iter().rposition(|&p| is_plant(p)).unwrap();
+            (state[first_plant..=last_plant].to_vec(), pos + first_plant as i64)
+        },
+        None => (vec![], 0),
+    }
+}
+
+fn next_gen(row: &Row, rules: &Rules) -> Row {
+    if row.0.is_empty() { return row.clone(); }
+    trim(&step(&row.0, rules), row.1 - rules.reach() as i64)
+}
+
+fn plant_machine(initial_state: &[u8], rules: &Rules, gens: usize) -> Row {
+    let mut row = trim(initial_state, 0);
     for _ in 0..gens {
-        let first_plant = state.iter().position(|&p| is_plant(p)).unwrap();
-        let last_plant = state.iter().rposition(|&p| is_plant(p)).unwrap();
-        let next = step(&state[first_plant..=last_plant], &rules);
-        pos = pos - 2 + first_plant as i64;
-        state = next;
+        row = next_gen(&row, rules);
+    }
+    row
+}
+
+// Find the state after any number of generations. Once the pattern of
+// plants repeats (usually as a glider moving a fixed distance every
+// generation), later generations are the same patterns shifted by a
+// constant offset each period. Give up if that doesn't happen within
+// limit generations.
+fn plant_machine_long(initial_state: &[u8], rules: &Rules, gens: usize, limit: usize)
+    -> Result<Row, String>
+{
+    if is_plant(rules.outcome(&vec![b'.'; rules.width])) {
+        return Err("empty pots grow plants, so the row is infinite".to_string());
     }
-    (state, pos)
+    let start = trim(initial_state, 0);
+    let repeat = cycle::hashed(&start, |row| next_gen(row, rules), |row| row.0.clone(), |row| row.1,
+                               limit.min(gens));
+    let Repeat { cycle, first, state } = match repeat {
+        Ok(repeat) => repeat,
+        Err(row) if gens <= limit => return Ok(row),
+        Err(_) => return Err(format!("no steady state within {} generations", limit)),
+    };
+
+    // The state is at generation mu + lambda, the pattern's first repeat.
+    // Run on to the same point in the cycle as gens, then move the pattern
+    // as far as it moves in the remaining whole cycles.
+    let shift = state.1 - first;
+    let rest = gens - cycle.mu - cycle.lambda;
+    let (pattern, pos) = (cycle.mu..cycle.equivalent_step(gens)).fold(state, |row, _| next_gen(&row, rules));
+    Ok((pattern, pos + (rest / cycle.lambda) as i64 * shift))
 }
 
 fn score(state: &[u8], left_pos: i64) -> i64 {
@@ -56,17 +157,19 @@ fn solve(input: &str) -> (i64, i64) {
     let (state20, pos20) = plant_machine(initial_state, &rules, 20);
     let part1 = score(&state20, pos20);
 
-    let (st1000, pos1000) = plant_machine(initial_state, &rules, 1000);
-    let (st2000, pos2000) = plant_machine(initial_state, &rules, 2000);
-    assert_eq!(st1000, st2000);
-
-    let pos_x = (50_000_000 - 1) * (pos2000 - pos1000) as usize + pos1000 as usize;
-    let score_x = score(&st1000, pos_x as i64);
+    let (state_x, pos_x) = plant_machine_long(initial_state, &rules, 50_000_000_000, 10_000)
+        .unwrap_or_else(|err| panic!("can't find part 2: {}", err));
+    let score_x = score(&state_x, pos_x);
 
     (part1, score_x)
 }
 
 pub fn run(input: &str) {
+    let (_, rules) = parse_input(input);
+    for warning in rules.check() {
+        println!("warning: {}", warning);
+    }
+
     let (part1, part2) = solve(input);
     println!("the solution to part 1 is {}", part1);
     println!("the solution to part 2 is {}", part2);
@@ -99,8 +202,9 @@ initial state: #..#.#..##......###...###
     fn parsing() {
         let (init, rules) = parse_input(EXAMPLE);
         assert_eq!(init, b"#..#.#..##......###...###");
-        assert_eq!(rules.get("...##".as_bytes()), Some(&b'#'));
-        assert_eq!(rules.get("####.".as_bytes()), Some(&b'#'));
+        assert_eq!(rules.get("...##".as_bytes()), Some(b'#'));
+        assert_eq!(rules.get("####.".as_bytes()), Some(b'#'));
+        assert_eq!(rules.get(".....".as_bytes()), None);
     }
 
     #[test]
@@ -111,6 +215,66 @@ initial state: #..#.#..##......###...###
         assert_eq!(325, part1);
     }
 
+    #[test]
+    fn steady_state() {
+        let (init, rules) = parse_input(EXAMPLE);
+        for &gens in &[20, 150, 1234] {
+            assert_eq!(plant_machine(init, &rules, gens),
+                       plant_machine_long(init, &rules, gens, 200).unwrap());
+        }
+        let (st, pos) = plant_machine_long(init, &rules, 50_000_000_000, 200).unwrap();
+        assert_eq!(999_999_999_374, score(&st, pos));
+        assert!(plant_machine_long(init, &rules, 1000, 50).is_err());
+    }
+
+    #[test]
+    fn wide_rules() {
+        // Width 3 rules: a plant appears wherever exactly one neighbor
+        // has a plant (rule 90).
+        let (init, rules) = parse_input("\
+initial state: #
+
+..# => #
+#.. => #
+default => .
+");
+        assert_eq!(3, rules.width);
+        let gens = (0..4).map(|g| plant_machine(init, &rules, g)).collect::<Vec<_>>();
+        assert_eq!(gens, vec![
+            (b"#".to_vec(), 0),
+            (b"#.#".to_vec(), -1),
+            (b"#...#".to_vec(), -2),
+            (b"#.#.#.#".to_vec(), -3),
+        ]);
+
+        // Width 7: a lone plant moves three pots right.
+        let (init, rules) = parse_input("\
+initial state: #
+
+#...... => #
+");
+        assert_eq!(plant_machine(init, &rules, 2), (b"#".to_vec(), 6));
+    }
+
+    #[test]
+    fn rule_check() {
+        let (_, rules) = parse_input(EXAMPLE);
+        assert_eq!(rules.check(), vec!["18 of 32 patterns have no rule and lead to .".to_string()]);
+        let (init, rules) = parse_input("\
+initial state: #
+
+... => #
+default => .
+");
+        assert_eq!(rules.check()[0], "... leads to a plant, so the row fills up forever");
+        assert!(plant_machine_long(init, &rules, 100, 100).is_err());
+
+        let widest = format!("initial state: #\n\n{} => #\n", "#".repeat(MAX_WIDTH));
+        let (_, rules) = parse_input(&widest);
+        assert_eq!(rules.check(), vec![format!("{} of {} patterns have no rule and lead to .",
+                                               usize::MAX / 2, 1usize << MAX_WIDTH)]);
+    }
+
     #[cfg(feature="test_real_input")]
     #[test]
     fn real_input() {
@@ -1,4 +1,5 @@
 use std::ops::Add;
+use crate::options::Options;
 
 #[derive(Clone, Copy, Debug, PartialEq)]
 struct Pos(i32, i32);
@@ -33,6 +34,7 @@ impl Add<Heading> for Pos {
 
 #[derive(Clone, Copy, Debug, PartialEq)]
 struct Cart {
+    id: usize,
     pos: Pos,
     heading: Heading,
     turns: u32,
@@ -40,8 +42,17 @@ struct Cart {
 }
 
 impl Cart {
-    fn new(pos: Pos, heading: Heading) -> Self {
-        Cart { pos, heading, turns: 0, dead: false }
+    fn new(id: usize, pos: Pos, heading: Heading) -> Self {
+        Cart { id, pos, heading, turns: 0, dead: false }
+    }
+
+    fn symbol(&self) -> u8 {
+        match self.heading {
+            Heading( 0,-1) => b'^',
+            Heading( 0, 1) => b'v',
+            Heading(-1, 0) => b'<',
+            _              => b'>',
+        }
     }
 
     fn next_pos(&self) -> Pos {
@@ -66,10 +77,19 @@ impl Cart {
     }
 }
 
+// Crash between two carts during the given tick (counting from 1).
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct Crash {
+    tick: usize,
+    pos: Pos,
+    carts: (usize, usize),
+}
+
 #[derive(Clone)]
 struct Simulation {
     grid: Vec<Vec<u8>>,
     carts: Vec<Cart>,
+    tick: usize,
 }
 
 fn parse_pos(c: u8) -> (u8, Option<Heading>) {
@@ -88,23 +108,88 @@ fn parse_input(s: &str) -> Simulation {
         line.bytes().enumerate().map(|(x, c)| {
             let (t, h) = parse_pos(c);
             if let Some(heading) = h {
-                carts.push(Cart::new(Pos(x as i32, y as i32), heading));
+                carts.push(Cart::new(carts.len(), Pos(x as i32, y as i32), heading));
             }
             t
         }).collect()
     ).collect();
-    Simulation { grid, carts }
+    Simulation { grid, carts, tick: 0 }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum TrackError {
+    DanglingRail(Pos),
+    BadIntersection(Pos),
+    CartOffTrack(usize, Pos),
+}
+
+const NORTH: Heading = Heading( 0,-1);
+const SOUTH: Heading = Heading( 0, 1);
+const EAST: Heading  = Heading( 1, 0);
+const WEST: Heading  = Heading(-1, 0);
+
+// The ways a piece of track can be laid, as the directions each way
+// connects to. A curve can be either of two corners.
+fn track_arms(track: u8) -> &'static [&'static [Heading]] {
+    match track {
+        b'-'  => &[&[EAST, WEST]],
+        b'|'  => &[&[NORTH, SOUTH]],
+        b'+'  => &[&[NORTH, SOUTH, EAST, WEST]],
+        b'/'  => &[&[EAST, SOUTH], &[WEST, NORTH]],
+        b'\\' => &[&[WEST, SOUTH], &[EAST, NORTH]],
+        _     => &[],
+    }
 }
 
 impl Simulation {
-    fn move_carts(&mut self) -> Vec<Pos> {
+    fn track_at(&self, pos: Pos) -> u8 {
+        if pos.0 < 0 || pos.1 < 0 { return b' '; }
+        self.grid.get(pos.1 as usize)
+            .and_then(|row| row.get(pos.0 as usize))
+            .cloned()
+            .unwrap_or(b' ')
+    }
+
+    // Check that every arm of every piece of track meets a piece that
+    // connects back. Carts are reported instead of the track under them.
+    fn validate(&self) -> Vec<TrackError> {
+        let connects = |pos: Pos, arm: Heading| {
+            let from = Heading(-arm.0, -arm.1);
+            track_arms(self.track_at(pos + arm)).iter().any(|arms| arms.contains(&from))
+        };
+        let mut errors = vec![];
+        for (y, row) in self.grid.iter().enumerate() {
+            for (x, &track) in row.iter().enumerate() {
+                let pos = Pos(x as i32, y as i32);
+                let ways = track_arms(track);
+                if ways.is_empty() || ways.iter().any(|arms| arms.iter().all(|&a| connects(pos, a))) {
+                    continue;
+                }
+                errors.push(if let Some(cart) = self.carts.iter().find(|c| c.pos == pos) {
+                    TrackError::CartOffTrack(cart.id, pos)
+                } else if track == b'+' {
+                    TrackError::BadIntersection(pos)
+                } else {
+                    TrackError::DanglingRail(pos)
+                });
+            }
+        }
+        errors
+    }
+
+    fn move_carts(&mut self) -> Vec<Crash> {
         let mut crashes = vec![];
+        self.tick += 1;
         self.carts.sort_unstable_by_key(|c| (c.pos.1, c.pos.0));
         for i in 0..self.carts.len() {
             if self.carts[i].dead { continue; }
             let new_pos = self.carts[i].next_pos();
             if let Some(j) = self.carts.iter().position(|c2| !c2.dead && c2.pos == new_pos) {
-                crashes.push(new_pos);
+                crashes.push(Crash {
+                    tick: self.tick,
+                    pos: new_pos,
+                    carts: (self.carts[i].id, self.carts[j].id),
+                });
                 self.carts[i].dead = true;
                 self.carts[j].dead = true;
             } else {
@@ -115,47 +200,152 @@ impl Simulation {
         crashes
     }
 
-    fn find_first_crash(&mut self) -> Pos {
+    fn live_carts(&self) -> impl Iterator<Item=&Cart> {
+        self.carts.iter().filter(|c| !c.dead)
+    }
+
+    // Everything that determines where the carts go next. Cart ids are
+    // left out since they don't affect the outcome.
+    fn cart_state(&self) -> Vec<(Pos, Heading, u32)> {
+        let mut state = self.live_carts()
+            .map(|c| (c.pos, c.heading, c.turns % 3))
+            .collect::<Vec<_>>();
+        state.sort_unstable_by_key(|&(pos, _, _)| (pos.1, pos.0));
+        state
+    }
+
+    // Move carts until done returns true, recording crashes in log.
+    // Return false if the carts return to an earlier state first, since
+    // then they will repeat the same moves forever. Repeats are found by
+    // Brent's method: compare against a saved state that is replaced
+    // after 1, 2, 4, 8... ticks, so no history needs to be kept.
+    fn run_until<F>(&mut self, log: &mut Vec<Crash>, done: F) -> bool
+        where F: Fn(&Simulation, &[Crash]) -> bool
+    {
+        let mut saved = self.cart_state();
+        let mut power = 1;
+        let mut lam = 0;
         loop {
-            let crashes = self.move_carts();
-            if !crashes.is_empty() {
-                return crashes[0];
+            log.extend(self.move_carts());
+            if done(self, log) {
+                return true;
+            }
+            let state = self.cart_state();
+            if state == saved {
+                return false;
+            }
+            lam += 1;
+            if lam == power {
+                saved = state;
+                power *= 2;
+                lam = 0;
             }
         }
     }
 
-    fn find_last_cart(&mut self) -> Pos {
-        let mut num_carts = self.carts.len();
-        // println!("started with {} carts", num_carts);
-        for _tick in 0.. {
-            // println!("tick {}", _tick);
-            let crashes = self.move_carts();
-            num_carts -= 2*crashes.len();
-            if num_carts == 1 {
-                // println!("stopping at tick {}", _tick);
-                return self.carts.iter().find(|c| !c.dead).unwrap().pos;
-            }
+    fn find_first_crash(&mut self) -> Option<Pos> {
+        let mut log = vec![];
+        if self.run_until(&mut log, |_, log| !log.is_empty()) {
+            Some(log[0].pos)
+        } else {
+            None
+        }
+    }
+
+    fn find_last_cart(&mut self) -> Option<Pos> {
+        self.run_until(&mut vec![], |sim, _| sim.live_carts().count() <= 1);
+        let mut live = self.live_carts();
+        match (live.next(), live.next()) {
+            (Some(cart), None) => Some(cart.pos),
+            _ => None,
         }
-        unreachable!()
+    }
+
+    // Run until at most one cart is left or max_ticks ticks have passed,
+    // recording every crash along the way. Return false if the carts
+    // would instead go on forever.
+    fn crash_log(&mut self, max_ticks: usize) -> (Vec<Crash>, bool) {
+        let mut log = vec![];
+        if self.tick >= max_ticks {
+            return (log, true);
+        }
+        let ends = self.run_until(&mut log, |sim, _| sim.tick >= max_ticks || sim.live_carts().count() <= 1);
+        (log, ends)
+    }
+
+    fn advance_to(&mut self, tick: usize) {
+        while self.tick < tick {
+            self.move_carts();
+        }
+    }
+
+    // Draw the track with the live carts on it.
+    fn render(&self) -> String {
+        let mut grid = self.grid.clone();
+        for cart in self.carts.iter().filter(|c| !c.dead) {
+            grid[cart.pos.1 as usize][cart.pos.0 as usize] = cart.symbol();
+        }
+        grid.into_iter()
+            .map(|row| String::from_utf8(row).unwrap() + "\n")
+            .collect()
     }
 }
 
 fn solve(input: &str) -> (String, String) {
     let sim = parse_input(input);
 
-    let first_crash = sim.clone().find_first_crash();
-    let first_crash = format!("{},{}", first_crash.0, first_crash.1);
-
-    let last_cart = sim.clone().find_last_cart();
-    let last_cart = format!("{},{}", last_cart.0, last_cart.1);
+    let show = |pos: Option<Pos>| match pos {
+        Some(pos) => format!("{},{}", pos.0, pos.1),
+        None => "never".to_string(),
+    };
+    let first_crash = show(sim.clone().find_first_crash());
+    let last_cart = show(sim.clone().find_last_cart());
 
     (first_crash, last_cart)
 }
 
-pub fn run(input: &str) {
-    let (part1, part2) = solve(input);
-    println!("the solution to part 1 is {}", part1);
-    println!("the solution to part 2 is {}", part2);
+pub fn run(input: &str, options: &[String]) {
+    if let Err(err) = run_options(input, Options::new(options)) {
+        eprintln!("{}", err);
+    }
+}
+
+fn run_options(input: &str, options: Options) -> Result<(), String> {
+    let errors = parse_input(input).validate();
+    if !errors.is_empty() {
+        for error in errors {
+            println!("invalid track: {:?}", error);
+        }
+        return Ok(());
+    }
+
+    match options.mode() {
+        Some("log") => {
+            let mut sim = parse_input(input);
+            let (log, ends) = sim.crash_log(options.parse_or(1, "number of ticks", usize::MAX)?);
+            for crash in log {
+                println!("tick {}: carts {} and {} crash at {},{}",
+                    crash.tick, crash.carts.0, crash.carts.1, crash.pos.0, crash.pos.1);
+            }
+            if !ends {
+                println!("tick {}: carts are repeating earlier moves; this never terminates", sim.tick);
+            } else if sim.live_carts().count() > 1 {
+                println!("tick {}: stopped with {} carts left", sim.tick, sim.live_carts().count());
+            }
+        },
+        Some("show") => {
+            let mut sim = parse_input(input);
+            sim.advance_to(options.parse_or(1, "tick", 0)?);
+            println!("after tick {}:\n{}", sim.tick, sim.render());
+        },
+        Some(_) => return Err(options.unknown()),
+        None => {
+            let (part1, part2) = solve(input);
+            println!("the solution to part 1 is {}", part1);
+            println!("the solution to part 2 is {}", part2);
+        }
+    }
+    Ok(())
 }
 
 #[cfg(test)]
@@ -187,8 +377,8 @@ r"/>-<\
         assert_eq!(sim.grid[0][2], b'-');
         assert_eq!(sim.grid[3][9], b'|');
         assert_eq!(sim.carts, vec![
-            Cart { pos: Pos(2,0), heading: Heading(1,0), turns: 0, dead: false },
-            Cart { pos: Pos(9,3), heading: Heading(0,1), turns: 0, dead: false },
+            Cart { id: 0, pos: Pos(2,0), heading: Heading(1,0), turns: 0, dead: false },
+            Cart { id: 1, pos: Pos(9,3), heading: Heading(0,1), turns: 0, dead: false },
             ]);
     }
 
@@ -196,14 +386,81 @@ r"/>-<\
     fn example() {
         let mut sim = parse_input(EXAMPLE);
         let first_crash = sim.find_first_crash();
-        assert_eq!(Pos(7,3), first_crash);
+        assert_eq!(Some(Pos(7,3)), first_crash);
     }
 
     #[test]
     fn example2() {
         let mut sim = parse_input(EXAMPLE2);
         let last_cart = sim.find_last_cart();
-        assert_eq!(Pos(6,4), last_cart);
+        assert_eq!(Some(Pos(6,4)), last_cart);
+    }
+
+    #[test]
+    fn crashes() {
+        let mut sim = parse_input(EXAMPLE2);
+        assert_eq!(sim.crash_log(2), (vec![
+            Crash { tick: 1, pos: Pos(2,0), carts: (1, 0) },
+            Crash { tick: 1, pos: Pos(2,4), carts: (5, 4) },
+            Crash { tick: 1, pos: Pos(6,4), carts: (6, 3) },
+        ], true));
+        assert_eq!(2, sim.tick);
+        assert_eq!(sim.crash_log(2), (vec![], true));
+
+        let mut sim = parse_input(EXAMPLE2);
+        assert_eq!(sim.crash_log(usize::MAX), (vec![
+            Crash { tick: 1, pos: Pos(2,0), carts: (1, 0) },
+            Crash { tick: 1, pos: Pos(2,4), carts: (5, 4) },
+            Crash { tick: 1, pos: Pos(6,4), carts: (6, 3) },
+            Crash { tick: 3, pos: Pos(2,4), carts: (7, 2) },
+        ], true));
+        assert_eq!(3, sim.tick);
+    }
+
+    #[test]
+    fn rendering() {
+        let mut sim = parse_input(EXAMPLE);
+        assert_eq!(EXAMPLE, sim.render());
+        sim.advance_to(13);
+        assert_eq!(sim.render(),
+r"/---\
+|   |  /----\
+| /-+--v-\  |
+| | |  | |  |
+\-+-/  ^-+--/
+  \------/
+");
+    }
+
+    #[test]
+    fn validation() {
+        assert_eq!(parse_input(EXAMPLE).validate(), vec![]);
+        assert_eq!(parse_input(EXAMPLE2).validate(), vec![]);
+        let sim = parse_input(r"/--\  >
+|  |  |
+\-+/ -+
+");
+        assert_eq!(sim.validate(), vec![
+            TrackError::CartOffTrack(0, Pos(6,0)),
+            TrackError::DanglingRail(Pos(6,1)),
+            TrackError::BadIntersection(Pos(2,2)),
+            TrackError::DanglingRail(Pos(5,2)),
+            TrackError::BadIntersection(Pos(6,2)),
+        ]);
+    }
+
+    #[test]
+    fn never_ends() {
+        let sim = parse_input(r"/>\ /<\
+\-/ \-/
+");
+        assert_eq!(None, sim.clone().find_first_crash());
+        assert_eq!(None, sim.clone().find_last_cart());
+
+        // Two carts crash and none are left.
+        let mut sim = parse_input("->-<-\n");
+        assert_eq!(None, sim.find_last_cart());
+        assert_eq!(1, sim.tick);
     }
 
     #[cfg(feature="test_real_input")]
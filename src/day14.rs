@@ -1,49 +1,122 @@
+use crate::options::{self, Options};
+
 struct RecipeTracker {
-    recipes: Vec<usize>,
-    elf1: usize,
-    elf2: usize,
+    recipes: Vec<u8>,
+    elves: Vec<usize>,
 }
 
 impl RecipeTracker {
     fn new() -> Self {
-        RecipeTracker { recipes: vec![3,7], elf1: 0, elf2: 1 }
+        RecipeTracker::with_recipes(&[3,7], 2)
+    }
+
+    // Start from any list of recipes, with elves on the first few of
+    // them (going round again if there are more elves than recipes).
+    fn with_recipes(start: &[u8], elves: usize) -> Self {
+        assert!(!start.is_empty(), "no starting recipes");
+        RecipeTracker { recipes: start.to_vec(), elves: (0..elves).map(|i| i % start.len()).collect() }
     }
 
     fn step(&mut self) {
-        let sum = self.recipes[self.elf1] + self.recipes[self.elf2];
-        if sum >= 10 {
-            self.recipes.push(1);
+        let sum = self.elves.iter().map(|&i| self.recipes[i] as usize).sum::<usize>();
+        // With more than eleven elves the sum can have three digits.
+        let mut place = 1;
+        while place * 10 <= sum {
+            place *= 10;
+        }
+        while place > 0 {
+            self.recipes.push((sum / place % 10) as u8);
+            place /= 10;
+        }
+        for i in 0..self.elves.len() {
+            self.elves[i] = self.pick_new_recipe(self.elves[i]);
         }
-        self.recipes.push(sum % 10);
-        self.elf1 = self.pick_new_recipe(self.elf1);
-        self.elf2 = self.pick_new_recipe(self.elf2);
     }
 
     fn pick_new_recipe(&self, i: usize) -> usize {
-        (i + 1 + self.recipes[i]) % self.recipes.len()
+        (i + 1 + self.recipes[i] as usize) % self.recipes.len()
     }
 
     fn scores_after(&mut self, n: usize) -> String {
         while self.recipes.len() < n + 10 {
             self.step();
         }
-        self.recipes[n..n+10].iter().map(|d| d.to_string()).collect()
+        self.recipes[n..n+10].iter().map(|&d| char::from(b'0' + d)).collect()
     }
 
     fn scores_before(&mut self, target: &str) -> usize {
-        let target = target.bytes().map(|c| (c - b'0') as usize).collect::<Vec<_>>();
-        for i in 0.. {
-            // make sure we have enough recipes
-            while self.recipes.len() < i + target.len() {
+        self.find_scores(target, usize::MAX).unwrap()
+    }
+
+    // Feed the recipes to a matcher one at a time, making more as needed,
+    // until the target turns up. Some starting recipes never make some
+    // digits, so give up after looking through limit recipes.
+    fn find_scores(&mut self, target: &str, limit: usize) -> Option<usize> {
+        let target = digits(target);
+        if target.is_empty() {
+            return Some(0);
+        }
+        let mut matcher = Matcher::new(&target);
+        let mut i = 0;
+        while i < limit {
+            if i == self.recipes.len() {
                 self.step();
             }
+            if matcher.feed(self.recipes[i]) {
+                return Some(i + 1 - target.len());
+            }
+            i += 1;
+        }
+        None
+    }
+}
 
-            // check at i
-            if &self.recipes[i..i+target.len()] == target.as_slice() {
-                return i;
+fn digits(s: &str) -> Vec<u8> {
+    s.bytes().map(|c| c - b'0').collect()
+}
+
+// Knuth-Morris-Pratt matching over a stream of digits. After a mismatch
+// the matcher falls back to the longest prefix of the pattern that is
+// still matched, so no digit is looked at twice.
+struct Matcher<'a> {
+    pattern: &'a [u8],
+    // fallback[k] is the length of the longest proper prefix of
+    // pattern[..k+1] that is also a suffix of it.
+    fallback: Vec<usize>,
+    matched: usize,
+}
+
+impl<'a> Matcher<'a> {
+    fn new(pattern: &'a [u8]) -> Self {
+        let mut fallback = vec![0; pattern.len()];
+        let mut k = 0;
+        for i in 1..pattern.len() {
+            while k > 0 && pattern[i] != pattern[k] {
+                k = fallback[k-1];
+            }
+            if pattern[i] == pattern[k] {
+                k += 1;
             }
+            fallback[i] = k;
+        }
+        Matcher { pattern, fallback, matched: 0 }
+    }
+
+    // Returns true when the digits fed so far end with the pattern.
+    fn feed(&mut self, digit: u8) -> bool {
+        if self.pattern.is_empty() {
+            return true;
+        }
+        if self.matched == self.pattern.len() {
+            self.matched = self.fallback[self.matched-1];
         }
-        unreachable!()
+        while self.matched > 0 && self.pattern[self.matched] != digit {
+            self.matched = self.fallback[self.matched-1];
+        }
+        if self.pattern[self.matched] == digit {
+            self.matched += 1;
+        }
+        self.matched == self.pattern.len()
     }
 }
 
@@ -55,10 +128,41 @@ fn solve(input: &str) -> (String, usize) {
     (part1, part2)
 }
 
-pub fn run(input: &str) {
-    let (part1, part2) = solve(input);
-    println!("the solution to part 1 is {}", part1);
-    println!("the solution to part 2 is {}", part2);
+const SEARCH_LIMIT: usize = 100_000_000;
+
+pub fn run(input: &str, options: &[String]) {
+    if let Err(err) = run_options(input, Options::new(options)) {
+        eprintln!("{}", err);
+    }
+}
+
+fn run_options(input: &str, options: Options) -> Result<(), String> {
+    if options.mode().is_none() {
+        let (part1, part2) = solve(input);
+        println!("the solution to part 1 is {}", part1);
+        println!("the solution to part 2 is {}", part2);
+        return Ok(());
+    }
+
+    let mut start = vec![3,7];
+    let mut elves = 2;
+    for (name, value) in options.pairs()? {
+        match name {
+            "elves" => elves = options::parse(value, "number of elves")?,
+            "start" if !value.is_empty() && value.bytes().all(|c| c.is_ascii_digit()) => start = digits(value),
+            "start" => return Err(format!("invalid starting scores: {}", value)),
+            _ => return Err(format!("unknown option {}", name)),
+        }
+    }
+
+    let input = input.trim();
+    let mut tracker = RecipeTracker::with_recipes(&start, elves);
+    println!("the solution to part 1 is {}", tracker.scores_after(options::parse(input, "recipe count")?));
+    match tracker.find_scores(input, SEARCH_LIMIT) {
+        Some(n) => println!("the solution to part 2 is {}", n),
+        None => println!("part 2: {} is not in the first {} recipes", input, SEARCH_LIMIT),
+    }
+    Ok(())
 }
 
 #[cfg(test)]
@@ -83,6 +187,57 @@ mod tests {
         assert_eq!(2018, tracker.scores_before("59414"));
     }
 
+    #[test]
+    fn matcher() {
+        let stream = digits("1011010110110101101");
+        for pattern in &["1", "11", "1011", "10110", "0110101101", "111", "2", ""] {
+            let pattern = digits(pattern);
+            let mut matcher = Matcher::new(&pattern);
+            let ends = stream.iter().enumerate()
+                .filter(|&(_, &d)| matcher.feed(d))
+                .map(|(i, _)| i + 1)
+                .collect::<Vec<_>>();
+            let expected = (1..=stream.len())
+                .filter(|&end| end >= pattern.len() && stream[end-pattern.len()..end] == pattern[..])
+                .collect::<Vec<_>>();
+            assert_eq!(ends, expected, "{:?}", pattern);
+        }
+    }
+
+    #[test]
+    fn more_elves() {
+        let mut tracker = RecipeTracker::with_recipes(&[3,7], 2);
+        assert_eq!("5941429882", tracker.scores_after(2018));
+
+        // Three elves on 1, 2 and 3: the first step makes a 6 and they
+        // move 2, 3 and 4 places along.
+        let mut tracker = RecipeTracker::with_recipes(&[1,2,3], 3);
+        tracker.step();
+        assert_eq!(tracker.recipes, vec![1,2,3,6]);
+        assert_eq!(tracker.elves, vec![2,0,2]);
+
+        // Sums can reach three digits with enough elves.
+        let mut tracker = RecipeTracker::with_recipes(&[9], 12);
+        tracker.step();
+        assert_eq!(tracker.recipes, vec![9,1,0,8]);
+
+        for &(start, elves) in &[(&[1,2,3][..], 3), (&[5][..], 1), (&[9,9,9,1][..], 7)] {
+            let mut tracker = RecipeTracker::with_recipes(start, elves);
+            let after = tracker.scores_after(500);
+            let target = &after[3..8];
+            let found = tracker.scores_before(target);
+            let window = digits(target);
+            let expected = tracker.recipes.windows(5).position(|w| w == &window[..]).unwrap();
+            assert_eq!(found, expected);
+        }
+
+        // Three elves starting on 1, 2, 3 never make a 0.
+        let mut tracker = RecipeTracker::with_recipes(&[1,2,3], 3);
+        assert_eq!(tracker.find_scores("0", 100_000), None);
+        assert_eq!(tracker.find_scores("6", 100_000), Some(3));
+        assert_eq!(tracker.find_scores("", 100_000), Some(0));
+    }
+
     #[cfg(feature="test_real_input")]
     #[test]
     fn real_input() {
@@ -1,23 +1,30 @@
-use std::cmp::Ordering;
-use std::collections::HashSet;
+use std::cmp::Reverse;
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::fs;
+use std::mem;
 use std::ops::{Index, IndexMut};
-use crate::search::*;
+use crate::rng::Rng;
+use crate::options::{self, Options};
 
 #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
 struct Pos(i32, i32);
 
 impl Pos {
-    fn neighbors(self) -> impl Iterator<Item=Pos> {
-        vec![
-            Pos(self.0 - 1, self.1),
-            Pos(self.0,     self.1 - 1),
-            Pos(self.0,     self.1 + 1),
-            Pos(self.0 + 1, self.1),
-        ].into_iter()
+    // Adjacent squares in reading order. With diagonal movement enabled,
+    // all eight surrounding squares are adjacent.
+    fn neighbors(self, diagonal: bool) -> impl Iterator<Item=Pos> {
+        let offsets: &[(i32, i32)] = if diagonal {
+            &[(-1,-1), (-1,0), (-1,1), (0,-1), (0,1), (1,-1), (1,0), (1,1)]
+        } else {
+            &[(-1,0), (0,-1), (0,1), (1,0)]
+        };
+        offsets.iter().map(move |&(dr, dc)| Pos(self.0 + dr, self.1 + dc))
     }
 
-    fn dist(self, other: Pos) -> u32 {
-        (self.0 - other.0).abs() as u32 + (self.1 - other.1).abs() as u32
+    fn adjacent(self, other: Pos, diagonal: bool) -> bool {
+        let (dr, dc) = ((self.0 - other.0).abs(), (self.1 - other.1).abs());
+        if diagonal { dr.max(dc) == 1 } else { dr + dc == 1 }
     }
 }
 
@@ -47,15 +54,144 @@ impl IndexMut<Pos> for Grid {
 }
 
 #[derive(Clone, Copy, Debug, PartialEq)]
-enum Team { Elf, Goblin }
+enum Team { Elf, Goblin, Other(char) }
 
 impl Team {
+    fn from_symbol(c: char) -> Self {
+        match c {
+            'E' => Team::Elf,
+            'G' => Team::Goblin,
+            _   => Team::Other(c),
+        }
+    }
+
     fn symbol(self) -> char {
         match self {
             Team::Elf => 'E',
             Team::Goblin => 'G',
+            Team::Other(c) => c,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct TeamRules {
+    team: Team,
+    hp: u32,
+    atk: u32,
+}
+
+// How a unit picks among the enemies in range of its attack.
+// Remaining ties are always broken in reading order.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum TargetChoice {
+    FewestHp,
+    MostHp,
+    Strongest,
+    ReadingOrder,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+struct Rules {
+    teams: Vec<TeamRules>,
+    diagonal: bool,
+    target_choice: TargetChoice,
+}
+
+impl Default for Rules {
+    fn default() -> Self {
+        Rules {
+            teams: vec![
+                TeamRules { team: Team::Elf, hp: 200, atk: 3 },
+                TeamRules { team: Team::Goblin, hp: 200, atk: 3 },
+            ],
+            diagonal: false,
+            target_choice: TargetChoice::FewestHp,
+        }
+    }
+}
+
+impl Rules {
+    fn team(&self, symbol: char) -> Option<&TeamRules> {
+        self.teams.iter().find(|t| t.team.symbol() == symbol)
+    }
+
+    fn team_mut(&mut self, symbol: char) -> &mut TeamRules {
+        if self.team(symbol).is_none() {
+            let team = Team::from_symbol(symbol);
+            self.teams.push(TeamRules { team, hp: 200, atk: 3 });
+        }
+        self.teams.iter_mut().find(|t| t.team.symbol() == symbol).unwrap()
+    }
+}
+
+// Parse a rules file. The format is a small subset of TOML: top-level
+// keys set the movement and targeting rules, and a `[team.X]` section
+// sets the stats of the faction drawn as X on the map. Any faction not
+// mentioned keeps the default stats, and new factions can be added.
+//
+//     diagonal = true
+//     target = "most-hp"
+//
+//     [team.E]
+//     attack = 10
+//
+//     [team.O]
+//     hp = 300
+fn parse_rules(s: &str) -> Result<Rules, String> {
+    let mut rules = Rules::default();
+    let mut section: Option<char> = None;
+    for (lineno, line) in s.lines().enumerate() {
+        let line = line.split('#').next().unwrap().trim();
+        if line.is_empty() { continue; }
+        let err = |msg: &str| format!("line {}: {}", lineno + 1, msg);
+
+        if line.starts_with('[') && line.ends_with(']') {
+            let name = line[1..line.len()-1].trim();
+            let mut symbol = name.strip_prefix("team.").unwrap_or("").chars();
+            match (symbol.next(), symbol.next()) {
+                (Some(c), None) if c != '.' => {
+                    rules.team_mut(c);
+                    section = Some(c);
+                },
+                _ => return Err(err(&format!("invalid section [{}]", name))),
+            }
+            continue;
+        }
+
+        let mut kv = line.splitn(2, '=').map(str::trim);
+        let key = kv.next().unwrap();
+        let value = kv.next().ok_or_else(|| err("expected `key = value`"))?
+            .trim_matches('"');
+        let number = || value.parse::<u32>().map_err(|_| err(&format!("invalid number for {}", key)));
+        match (section, key) {
+            (None, "diagonal") => {
+                rules.diagonal = value.parse().map_err(|_| err("diagonal must be true or false"))?;
+            },
+            (None, "target") => {
+                rules.target_choice = match value {
+                    "fewest-hp" => TargetChoice::FewestHp,
+                    "most-hp" => TargetChoice::MostHp,
+                    "strongest" => TargetChoice::Strongest,
+                    "reading-order" => TargetChoice::ReadingOrder,
+                    _ => return Err(err(&format!("unknown target rule {}", value))),
+                };
+            },
+            // Units with no hit points would start out dead but still
+            // stand in the way, and teams that can't hurt anyone could
+            // fight forever.
+            (Some(c), "hp") => match number()? {
+                0 => return Err(err("hp must be at least 1")),
+                hp => rules.team_mut(c).hp = hp,
+            },
+            (Some(c), "attack") => match number()? {
+                0 => return Err(err("attack must be at least 1")),
+                atk => rules.team_mut(c).atk = atk,
+            },
+            _ => return Err(err(&format!("unknown key {}", key))),
         }
     }
+    Ok(rules)
 }
 
 #[derive(Clone, Copy, Debug, PartialEq)]
@@ -67,31 +203,33 @@ struct Unit {
 }
 
 impl Unit {
-    fn new(team: Team, pos: Pos) -> Self {
-        Unit { team, pos, atk: 3 , hp: 200}
+    fn new(stats: &TeamRules, pos: Pos) -> Self {
+        Unit { team: stats.team, pos, atk: stats.atk, hp: stats.hp }
     }
 }
 
-fn push_unit(units: &mut Vec<Unit>, team: Team, row: usize, col: usize) -> UnitID {
-    units.push(Unit::new(team, Pos(row as i32, col as i32)));
+fn push_unit(units: &mut Vec<Unit>, stats: &TeamRules, row: usize, col: usize) -> UnitID {
+    units.push(Unit::new(stats, Pos(row as i32, col as i32)));
     units.len() - 1
 }
 
 fn parse_input(s: &str) -> (Grid, Vec<Unit>) {
+    parse_map(s, &Rules::default())
+}
+
+fn parse_map(s: &str, rules: &Rules) -> (Grid, Vec<Unit>) {
     let mut units = Vec::new();
     let grid = s.lines().enumerate().map(|(row, line)|
         line.chars().enumerate().map(|(col, c)|
             match c {
-                'E' => {
-                    let uid = push_unit(&mut units, Team::Elf, row, col);
-                    GridContents::Unit(uid)
-                },
-                'G' => {
-                    let uid = push_unit(&mut units, Team::Goblin, row, col);
-                    GridContents::Unit(uid)
-                },
                 '.' => GridContents::Open,
-                _   => GridContents::Wall,
+                _   => match rules.team(c) {
+                    Some(stats) => {
+                        let uid = push_unit(&mut units, stats, row, col);
+                        GridContents::Unit(uid)
+                    },
+                    None => GridContents::Wall,
+                },
             }
         ).collect()
     ).collect();
@@ -126,82 +264,169 @@ fn grid_string(grid: &Grid, units: &[Unit]) -> String {
         }).collect()
 }
 
-#[derive(Clone, Debug, PartialEq, Eq)]
-struct PathSearchState {
-    pos: Pos,
-    steps: u32,
-    cost: u32,
-    target: Pos,
-    first_step: Option<Pos>,
+// Parameters for random battlefields. Walls are scattered inside a solid
+// border with the given density, then any open squares not connected to
+// the largest open region are walled in, so every unit can reach every
+// other unit (when not blocked by other units).
+#[derive(Clone, Copy, Debug)]
+struct MapParams {
+    width: usize,
+    height: usize,
+    wall_density: f64,
+    elves: usize,
+    goblins: usize,
 }
 
-impl Ord for PathSearchState {
-    fn cmp(&self, other: &Self) -> Ordering {
-        self.cost.cmp(&other.cost)
-            .then(self.target.cmp(&other.target))
-            .then(self.first_step.cmp(&other.first_step))
+impl Default for MapParams {
+    fn default() -> Self {
+        MapParams { width: 32, height: 32, wall_density: 0.3, elves: 10, goblins: 20 }
     }
 }
 
-impl PartialOrd for PathSearchState {
-    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
-        Some(self.cmp(other))
+// Mark every square connected to start, returning how many there are.
+fn flood_region(map: &[Vec<u8>], start: (usize, usize), labels: &mut [Vec<usize>], label: usize) -> usize {
+    let mut stack = vec![start];
+    labels[start.0][start.1] = label;
+    let mut size = 0;
+    while let Some((r, c)) = stack.pop() {
+        size += 1;
+        for &(nr, nc) in &[(r-1, c), (r, c-1), (r, c+1), (r+1, c)] {
+            if map[nr][nc] != b'#' && labels[nr][nc] == 0 {
+                labels[nr][nc] = label;
+                stack.push((nr, nc));
+            }
+        }
     }
+    size
 }
 
-impl PathSearchState {
-    fn new(pos: Pos) -> Self {
-        PathSearchState {
-            pos,
-            steps: 0,
-            cost: 0,
-            target: pos,
-            first_step: None,
+// Generate a random map in the puzzle's input format.
+fn generate_map(params: &MapParams, seed: u64) -> String {
+    let MapParams { width, height, wall_density, elves, goblins } = *params;
+    assert!(width >= 3 && height >= 3, "map too small");
+    assert!(elves + goblins <= (width - 2) * (height - 2), "too many units for map");
+    let mut rng = Rng::new(seed);
+    for _attempt in 0..100 {
+        let mut map: Vec<Vec<u8>> = (0..height).map(|r|
+            (0..width).map(|c| {
+                let border = r == 0 || c == 0 || r == height - 1 || c == width - 1;
+                if border || rng.chance(wall_density) { b'#' } else { b'.' }
+            }).collect()
+        ).collect();
+
+        // Label the open regions 1, 2, ... and find the largest.
+        let mut labels = vec![vec![0; width]; height];
+        let mut regions = 0;
+        let mut best: Option<(usize, usize)> = None;
+        for r in 0..height {
+            for c in 0..width {
+                if map[r][c] == b'#' || labels[r][c] != 0 { continue; }
+                regions += 1;
+                let size = flood_region(&map, (r, c), &mut labels, regions);
+                if best.is_none_or(|b| size > b.0) {
+                    best = Some((size, regions));
+                }
+            }
+        }
+        let region = match best {
+            Some((size, label)) if size >= elves + goblins => label,
+            _ => continue,
+        };
+
+        // Wall in everything else and place the units.
+        let mut open = vec![];
+        for r in 0..height {
+            for c in 0..width {
+                if labels[r][c] == region {
+                    open.push((r, c));
+                } else {
+                    map[r][c] = b'#';
+                }
+            }
         }
+        rng.shuffle(&mut open);
+        for (i, &(r, c)) in open.iter().take(elves + goblins).enumerate() {
+            map[r][c] = if i < elves { b'E' } else { b'G' };
+        }
+
+        return map.into_iter()
+            .map(|row| String::from_utf8(row).unwrap() + "\n")
+            .collect();
     }
+    panic!("could not generate a connected map with {:?}", params);
 }
 
-struct PathSearch<'a> {
-    grid: &'a Grid,
-    dests: &'a HashSet<Pos>,
+// For open squares, the number of steps to the nearest of a set of
+// destinations and which destination that is, found by one breadth-first
+// search from all of them at once. The buffers are kept from one search
+// to the next, and only the squares reached last time are cleared.
+struct DistanceMap {
+    nearest: Vec<Vec<Option<(u32, Pos)>>>,
+    reached: Vec<Pos>,
+    sources: Vec<Pos>,
+    queue: VecDeque<Pos>,
 }
 
-impl<'a> SearchSpec for PathSearch<'a> {
-    type State = PathSearchState;
-    type Token = Pos;
-
-    fn branch(&self, state: &Self::State) -> Vec<Self::State> {
-        state.pos.neighbors()
-            .filter(|&n| self.grid[n] == GridContents::Open)
-            .map(|pos| {
-                let (tdist, &target) = self.dests.iter()
-                    .map(|d| (pos.dist(*d), d))
-                    .min().unwrap();
-                let steps = state.steps + 1;
-                PathSearchState {
-                    pos,
-                    steps,
-                    cost: steps + tdist,
-                    target,
-                    first_step: state.first_step.or_else(|| Some(pos)),
-                }
-            }).collect()
+impl DistanceMap {
+    fn new() -> Self {
+        DistanceMap { nearest: vec![], reached: vec![], sources: vec![], queue: VecDeque::new() }
     }
 
-    fn is_goal(&self, state: &Self::State) -> bool {
-        self.dests.contains(&state.pos)
+    fn get(&self, pos: Pos) -> Option<(u32, Pos)> {
+        self.nearest[pos.0 as usize][pos.1 as usize]
     }
 
-    fn token(&self, state: &Self::State) -> Self::Token {
-        state.pos
+    fn set(&mut self, pos: Pos, nearest: (u32, Pos)) {
+        self.nearest[pos.0 as usize][pos.1 as usize] = Some(nearest);
+        self.reached.push(pos);
     }
-}
 
-fn choose_step(grid: &Grid, start: Pos, dests: &HashSet<Pos>) -> Option<Pos> {
-    if dests.is_empty() { return None };
-    let searcher = PathSearch { grid, dests };
-    best_first_search(searcher, PathSearchState::new(start))
-        .map(|st| st.first_step.expect("no first step"))
+    // Choose the destination closest to start, first in reading order on
+    // ties, then take the first step in reading order along a shortest
+    // path to it. The search stops once the squares next to start have
+    // been reached.
+    fn choose_step<I>(&mut self, grid: &Grid, start: Pos, dests: I, diagonal: bool) -> Option<Pos>
+        where I: Iterator<Item=Pos>
+    {
+        if self.nearest.len() != grid.0.len() {
+            self.nearest = grid.0.iter().map(|row| vec![None; row.len()]).collect();
+            self.reached.clear();
+        }
+        for pos in self.reached.drain(..) {
+            self.nearest[pos.0 as usize][pos.1 as usize] = None;
+        }
+
+        // Starting from the destinations in reading order, squares at each
+        // distance are queued in order of their nearest destination, so a
+        // square is always reached first from the earliest destination.
+        let mut sources = mem::take(&mut self.sources);
+        sources.clear();
+        sources.extend(dests);
+        sources.sort_unstable();
+        sources.dedup();
+        self.queue.clear();
+        for &dest in sources.iter() {
+            self.set(dest, (0, dest));
+            self.queue.push_back(dest);
+        }
+        self.sources = sources;
+        while let Some(pos) = self.queue.pop_front() {
+            // Every square as close as this one is already reached.
+            if pos.adjacent(start, diagonal) { break; }
+            let (d, dest) = self.get(pos).unwrap();
+            for n in pos.neighbors(diagonal) {
+                if self.get(n).is_none() && grid[n] == GridContents::Open {
+                    self.set(n, (d + 1, dest));
+                    self.queue.push_back(n);
+                }
+            }
+        }
+
+        start.neighbors(diagonal)
+            .filter_map(|n| self.get(n).map(|(d, dest)| (d, dest, n)))
+            .min()
+            .map(|(_, _, step)| step)
+    }
 }
 
 #[derive(Clone, Copy, Debug, PartialEq)]
@@ -214,12 +439,22 @@ enum FindMoveResult {
 struct Simulation {
     grid: Grid,
     units: Vec<Unit>,
+    rules: Rules,
+    // Reused by find_move from one call to the next.
+    paths: RefCell<DistanceMap>,
+    // Full rounds of combat completed.
+    rounds: u32,
     verbose: bool,
+    check_invariants: bool,
 }
 
 impl Simulation {
     fn new(grid: Grid, units: Vec<Unit>) -> Self {
-        Simulation { grid, units, verbose: false }
+        Simulation::with_rules(grid, units, Rules::default())
+    }
+
+    fn with_rules(grid: Grid, units: Vec<Unit>, rules: Rules) -> Self {
+        Simulation { grid, units, rules, paths: RefCell::new(DistanceMap::new()), rounds: 0, verbose: false, check_invariants: false }
     }
 
     fn move_unit(&mut self, uid: UnitID, new_pos: Pos) {
@@ -243,28 +478,35 @@ impl Simulation {
 
     fn find_move(&self, uid: UnitID) -> FindMoveResult {
         // Identify targets.
-        let mover = &self.units[uid];
-        let targets: Vec<&Unit> = self.units.iter()
+        let mover = self.units[uid];
+        let diagonal = self.rules.diagonal;
+        let mut targets = self.units.iter()
             .filter(|&target| target.hp != 0 && target.team != mover.team)
-            .collect();
-        if targets.is_empty() { return FindMoveResult::NoTargets; }
-
-        // Identify open squares in range of (adjacent to) targets.
-        let adj_squares: HashSet<Pos> = targets.into_iter()
-            .flat_map(|target| target.pos.neighbors())
-            .filter(|&pos| self.grid[pos] == GridContents::Open)
-            .collect();
-        match choose_step(&self.grid, mover.pos, &adj_squares) {
+            .peekable();
+        if targets.peek().is_none() { return FindMoveResult::NoTargets; }
+
+        // Find distances to the open squares in range of (adjacent to) targets.
+        let grid = &self.grid;
+        let adj_squares = targets
+            .flat_map(|target| target.pos.neighbors(diagonal))
+            .filter(|&pos| grid[pos] == GridContents::Open);
+        match self.paths.borrow_mut().choose_step(grid, mover.pos, adj_squares, diagonal) {
             Some(step) => FindMoveResult::Step(step),
             None => FindMoveResult::NoPath,
         }
     }
 
     fn find_attack_target(&self, attacker: UnitID) -> Option<UnitID> {
-        self.units[attacker].pos.neighbors()
+        let targets = self.units[attacker].pos.neighbors(self.rules.diagonal)
             .filter_map(|pos| if let GridContents::Unit(uid) = self.grid[pos] { Some(uid) } else { None })
-            .filter(|&target| self.units[target].team != self.units[attacker].team)
-            .min_by_key(|&target| (self.units[target].hp, self.units[target].pos))
+            .filter(|&target| self.units[target].team != self.units[attacker].team);
+        let units = &self.units;
+        match self.rules.target_choice {
+            TargetChoice::FewestHp => targets.min_by_key(|&t| (units[t].hp, units[t].pos)),
+            TargetChoice::MostHp => targets.min_by_key(|&t| (Reverse(units[t].hp), units[t].pos)),
+            TargetChoice::Strongest => targets.min_by_key(|&t| (Reverse(units[t].atk), units[t].pos)),
+            TargetChoice::ReadingOrder => targets.min_by_key(|&t| units[t].pos),
+        }
     }
 
     // Return true if combat should stop.
@@ -298,13 +540,34 @@ impl Simulation {
         false
     }
 
+    // Check that the grid and the unit list agree: every living unit is on
+    // its own square, dead units are off the grid, and no unit has more
+    // hit points than it started with.
+    fn assert_invariants(&self) {
+        for (uid, unit) in self.units.iter().enumerate() {
+            let max_hp = self.rules.team(unit.team.symbol()).unwrap().hp;
+            assert!(unit.hp <= max_hp, "unit {} has {} hp", uid, unit.hp);
+            if unit.hp > 0 {
+                assert_eq!(GridContents::Unit(uid), self.grid[unit.pos], "unit {} not on grid", uid);
+            }
+        }
+        for (row, line) in self.grid.0.iter().enumerate() {
+            for (col, &square) in line.iter().enumerate() {
+                if let GridContents::Unit(uid) = square {
+                    let unit = &self.units[uid];
+                    assert!(unit.hp > 0, "dead unit {} on grid", uid);
+                    assert_eq!(Pos(row as i32, col as i32), unit.pos, "unit {} in two places", uid);
+                }
+            }
+        }
+    }
+
     fn simulate(&mut self, stop_on_elf_death: bool) -> (u32, bool) {
         let initial_elves = self.units.iter().filter(|u| u.team == Team::Elf).count();
         let mut active_units: Vec<UnitID> = (0..self.units.len()).collect();
-        let mut round = 0u32;
         loop {
             if self.verbose {
-                println!("-- begin round {} -- {} active units --", round, active_units.len());
+                println!("-- begin round {} -- {} active units --", self.rounds, active_units.len());
                 println!("{}", grid_string(&self.grid, &self.units));
             }
 
@@ -312,26 +575,32 @@ impl Simulation {
             active_units.sort_unstable_by_key(|&u| self.units[u].pos);
 
             // Take turns. If any unit indicates combat should stop, then stop.
-            if active_units.iter().any(|&uid| self.take_turn(uid, stop_on_elf_death)) {
+            if active_units.iter().any(|&uid| {
+                let stop = self.take_turn(uid, stop_on_elf_death);
+                if self.check_invariants {
+                    self.assert_invariants();
+                }
+                stop
+            }) {
                 break;
             }
 
             // Remove dead units from active list.
             active_units.retain(|&uid| self.units[uid].hp > 0);
 
-            round += 1;
+            self.rounds += 1;
         }
 
         let hp_total = self.units.iter().map(|u| u.hp).sum::<u32>();
-        let outcome = round * hp_total;
+        let outcome = self.rounds * hp_total;
 
         if self.verbose {
             active_units.retain(|&u| self.units[u].hp > 0);
             println!("-- final state -- {} active units --", active_units.len());
             println!("{}", grid_string(&self.grid, &self.units));
-            println!("Combat ends after {} full rounds", round);
+            println!("Combat ends after {} full rounds", self.rounds);
             println!("{:?} win with {} total hit points left", self.units[active_units[0]].team, hp_total);
-            println!("Outcome: {} * {} = {}", round, hp_total, outcome);
+            println!("Outcome: {} * {} = {}", self.rounds, hp_total, outcome);
         }
 
         let final_elves = self.units.iter().filter(|u| u.hp > 0 && u.team == Team::Elf).count();
@@ -363,10 +632,63 @@ fn solve(input: &str) -> (u32, u32) {
     (outcome, outcome2)
 }
 
-pub fn run(input: &str) {
-    let (part1, part2) = solve(input);
-    println!("the solution to part 1 is {}", part1);
-    println!("the solution to part 2 is {}", part2);
+// Run a single battle under custom rules and report the outcome
+// along with the surviving team.
+fn run_scenario(input: &str, rules: Rules) -> (u32, Team) {
+    let (grid, units) = parse_map(input, &rules);
+    let mut sim = Simulation::with_rules(grid, units, rules);
+    let (outcome, _) = sim.simulate(false);
+    let winner = sim.units.iter().find(|u| u.hp > 0).unwrap().team;
+    (outcome, winner)
+}
+
+// Simulate a battle on a random map, checking invariants after every turn.
+fn run_generated(params: &MapParams, seed: u64) -> String {
+    let map = generate_map(params, seed);
+    let (grid, units) = parse_input(&map);
+    let mut sim = Simulation::new(grid, units);
+    sim.check_invariants = true;
+    let (outcome, _) = sim.simulate(false);
+    format!("{}outcome {}", map, outcome)
+}
+
+pub fn run(input: &str, options: &[String]) {
+    if let Err(err) = run_options(input, Options::new(options)) {
+        eprintln!("{}", err);
+    }
+}
+
+fn run_options(input: &str, options: Options) -> Result<(), String> {
+    match options.mode() {
+        Some("generate") => {
+            let mut params = MapParams::default();
+            let seed = options.parse_or(1, "seed", 0)?;
+            if let Some(size) = options.get(2) {
+                params.width = options::parse(size, "map size")?;
+                params.height = params.width;
+            }
+            if let Some(percent) = options.get(3) {
+                params.wall_density = options::parse::<f64>(percent, "wall percentage")? / 100.0;
+            }
+            params.elves = options.parse_or(4, "number of elves", params.elves)?;
+            params.goblins = options.parse_or(5, "number of goblins", params.goblins)?;
+            println!("{}", run_generated(&params, seed));
+        }
+        Some(rules_file) => {
+            let rules = fs::read_to_string(rules_file)
+                .map_err(|err| err.to_string())
+                .and_then(|s| parse_rules(&s))
+                .map_err(|err| format!("could not load rules from {}: {}", rules_file, err))?;
+            let (outcome, winner) = run_scenario(input, rules);
+            println!("{:?} win, the outcome is {}", winner, outcome);
+        }
+        None => {
+            let (part1, part2) = solve(input);
+            println!("the solution to part 1 is {}", part1);
+            println!("the solution to part 2 is {}", part2);
+        }
+    }
+    Ok(())
 }
 
 #[cfg(test)]
@@ -525,6 +847,90 @@ mod tests {
         assert_eq!(FindMoveResult::Step(Pos(2,2)), sim.find_move(0))
     }
 
+    #[test]
+    fn distance_map() {
+        let (grid, _) = parse_input("\
+#######
+#E..#.#
+#.#...#
+#...#G#
+#######
+");
+        let mut dist = DistanceMap::new();
+        let dests = [Pos(1,5), Pos(3,1), Pos(1,5)];
+        assert_eq!(Some(Pos(2,1)), dist.choose_step(&grid, Pos(1,1), dests.iter().cloned(), false));
+        assert_eq!(Some((0, Pos(1,5))), dist.get(Pos(1,5)));
+        assert_eq!(Some((1, Pos(3,1))), dist.get(Pos(2,1)));
+        assert_eq!(Some((1, Pos(1,5))), dist.get(Pos(2,5)));
+        assert_eq!(None, dist.get(Pos(1,1)));
+        assert_eq!(None, dist.get(Pos(3,5)));
+
+        // The search stops once E's neighbors are reached.
+        let dests = [Pos(1,5), Pos(1,3)];
+        assert_eq!(Some(Pos(1,2)), dist.choose_step(&grid, Pos(1,1), dests.iter().cloned(), false));
+        assert_eq!(Some((1, Pos(1,3))), dist.get(Pos(1,2)));
+        assert_eq!(None, dist.get(Pos(2,4)));
+        assert_eq!(None, dist.get(Pos(2,1)));
+
+        // Squares as far from both destinations go toward the first in
+        // reading order.
+        assert_eq!(Some(Pos(3,2)), dist.choose_step(&grid, Pos(3,1), dests.iter().cloned(), false));
+        assert_eq!(Some((2, Pos(1,3))), dist.get(Pos(2,4)));
+        assert_eq!(Some((3, Pos(1,3))), dist.get(Pos(3,2)));
+        assert_eq!(None, dist.choose_step(&grid, Pos(1,1), [].iter().cloned(), false));
+    }
+
+    #[test]
+    fn generator() {
+        let params = MapParams { width: 20, height: 12, wall_density: 0.4, elves: 4, goblins: 6 };
+        let map = generate_map(&params, 42);
+        assert_eq!(map, generate_map(&params, 42));
+        assert_eq!(12, map.lines().count());
+        assert!(map.lines().all(|line| line.len() == 20));
+        let (grid, units) = parse_input(&map);
+        assert_eq!(4, units.iter().filter(|u| u.team == Team::Elf).count());
+        assert_eq!(6, units.iter().filter(|u| u.team == Team::Goblin).count());
+
+        // Every non-wall square is reachable from the first unit.
+        let map: Vec<Vec<u8>> = map.lines().map(|line| line.bytes().collect()).collect();
+        let mut labels = vec![vec![0; 20]; 12];
+        let start = (units[0].pos.0 as usize, units[0].pos.1 as usize);
+        let reached = flood_region(&map, start, &mut labels, 1);
+        let open = grid.0.iter().flat_map(|row| row.iter())
+            .filter(|&&c| c != GridContents::Wall)
+            .count();
+        assert_eq!(open, reached);
+    }
+
+    #[test]
+    fn random_battles() {
+        let mut rng = Rng::new(15);
+        for seed in 0..40 {
+            let size = 6 + rng.below(10);
+            let params = MapParams {
+                width: size,
+                height: size,
+                wall_density: rng.below(40) as f64 / 100.0,
+                elves: 1 + rng.below(5),
+                goblins: 1 + rng.below(5),
+            };
+            let map = generate_map(&params, seed);
+            let (grid, units) = parse_input(&map);
+            let mut sim = Simulation::new(grid, units);
+            sim.check_invariants = true;
+            let (outcome, elf_victory) = sim.simulate(false);
+
+            // One team is left standing, and the outcome counts full rounds.
+            let survivors = sim.units.iter().filter(|u| u.hp > 0).collect::<Vec<_>>();
+            let winner = survivors[0].team;
+            assert!(survivors.iter().all(|u| u.team == winner), "{}", map);
+            assert_eq!(elf_victory, winner == Team::Elf && survivors.len() == params.elves, "{}", map);
+            let hp = survivors.iter().map(|u| u.hp).sum::<u32>();
+            assert_eq!(outcome, sim.rounds * hp, "{}", map);
+            assert_eq!(format!("{}outcome {}", map, outcome), run_generated(&params, seed));
+        }
+    }
+
     #[test]
     fn attack1() {
         // Simulate a complete round. The elf should attack the goblin directly above.
@@ -544,6 +950,75 @@ mod tests {
         ])
     }
 
+    #[test]
+    fn rules_parsing() {
+        let rules = parse_rules("\
+diagonal = true
+target = \"most-hp\"   # prefer the healthiest enemy
+
+[team.E]
+attack = 10
+
+[team.O]
+hp = 300
+").unwrap();
+        assert!(rules.diagonal);
+        assert_eq!(TargetChoice::MostHp, rules.target_choice);
+        assert_eq!(rules.teams, vec![
+            TeamRules { team: Team::Elf, hp: 200, atk: 10 },
+            TeamRules { team: Team::Goblin, hp: 200, atk: 3 },
+            TeamRules { team: Team::Other('O'), hp: 300, atk: 3 },
+        ]);
+        assert!(parse_rules("[team.E]\nspeed = 2\n").is_err());
+        assert!(parse_rules("target = \"random\"\n").is_err());
+        assert_eq!(parse_rules("[team.G]\nhp = 0\n").unwrap_err(), "line 2: hp must be at least 1");
+        assert_eq!(parse_rules("[team.E]\nattack = 0\n").unwrap_err(), "line 2: attack must be at least 1");
+    }
+
+    #[test]
+    fn diagonal_attack() {
+        let map = "\
+####
+#G.#
+#.E#
+####
+";
+        let (grid, units) = parse_input(map);
+        let sim = Simulation::new(grid, units);
+        assert_eq!(None, sim.find_attack_target(1));
+
+        let rules = Rules { diagonal: true, ..Rules::default() };
+        let (grid, units) = parse_map(map, &rules);
+        let sim = Simulation::with_rules(grid, units, rules);
+        assert_eq!(Some(0), sim.find_attack_target(1));
+    }
+
+    #[test]
+    fn target_choice() {
+        let (grid, mut units) = parse_input("\
+#####
+#.G.#
+#GEG#
+#####
+");
+        units[0].hp = 150;
+        units[3].hp = 100;
+        let mut sim = Simulation::new(grid, units);
+        assert_eq!(Some(3), sim.find_attack_target(2));
+        sim.rules.target_choice = TargetChoice::MostHp;
+        assert_eq!(Some(1), sim.find_attack_target(2));
+        sim.rules.target_choice = TargetChoice::ReadingOrder;
+        assert_eq!(Some(0), sim.find_attack_target(2));
+    }
+
+    #[test]
+    fn three_factions() {
+        let rules = parse_rules("[team.O]\nattack = 200\n").unwrap();
+        let (outcome, winner) = run_scenario("#######\n#E.O.G#\n#######\n", rules);
+        assert_eq!(Team::Other('O'), winner);
+        assert_eq!(2 * 194, outcome);
+    }
+
     #[cfg(feature="test_real_input")]
     #[test]
     fn real_input() {
@@ -1,13 +1,19 @@
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
 use regex::Regex;
+use crate::options::Options;
 
 const SHOW_FINAL_GRID: bool = false;
+const RECURSIVE_ENGINE: bool = false;
 
 type Vein = (usize, usize, usize, usize);
+type Spring = (usize, usize);
 
 fn parse_input(s: &str) -> Vec<Vein> {
     let re_vein_v = Regex::new(r"x=(\d+), y=(\d+)..(\d+)").unwrap();
     let re_vein_h = Regex::new(r"y=(\d+), x=(\d+)..(\d+)").unwrap();
-    s.lines().map(|line| {
+    s.lines().filter(|line| !line.starts_with("spring")).map(|line| {
         if let Some(caps) = re_vein_v.captures(line) {
             let x = caps[1].parse().unwrap();
             let y0 = caps[2].parse().unwrap();
@@ -24,6 +30,18 @@ fn parse_input(s: &str) -> Vec<Vein> {
     }).collect()
 }
 
+// Springs are given by lines like "spring x=500, y=0" anywhere in the
+// input. Without any, there is just the one spring at x=500, y=0.
+fn parse_springs(s: &str) -> Vec<Spring> {
+    let re_spring = Regex::new(r"^spring x=(\d+), y=(\d+)$").unwrap();
+    let springs = s.lines().filter(|line| line.starts_with("spring")).map(|line| {
+        let caps = re_spring.captures(line)
+            .unwrap_or_else(|| panic!("invalid spring in input: {}", line));
+        (caps[1].parse().unwrap(), caps[2].parse().unwrap())
+    }).collect::<Vec<_>>();
+    if springs.is_empty() { vec![(500, 0)] } else { springs }
+}
+
 fn region_bounds(veins: &[Vein]) -> (usize, usize, usize, usize) {
     let min_x = veins.iter().map(|v| v.0).min().unwrap();
     let max_x = veins.iter().map(|v| v.1).max().unwrap();
@@ -58,6 +76,9 @@ impl Square {
 
 type Grid = Vec<Vec<Square>>;
 
+// A square that changed to the given kind.
+type Change = (usize, usize, Square);
+
 fn show_grid(grid: &[Vec<Square>]) {
     for row in grid.iter() {
         let line: String = row.iter().map(|s| match s {
@@ -70,6 +91,41 @@ fn show_grid(grid: &[Vec<Square>]) {
     }
 }
 
+fn square_color(s: Square) -> [u8; 3] {
+    match s {
+        Square::Sand => [0xf0, 0xe0, 0xb0],
+        Square::Clay => [0x80, 0x48, 0x18],
+        Square::Water => [0x10, 0x40, 0xc0],
+        Square::WetSand => [0x80, 0xc0, 0xf0],
+    }
+}
+
+// Write the grid as a binary PPM image, one pixel per square.
+fn write_ppm<W: Write>(grid: &[Vec<Square>], mut out: W) -> io::Result<()> {
+    write!(out, "P6\n{} {}\n255\n", grid[0].len(), grid.len())?;
+    for &s in grid.iter().flat_map(|row| row.iter()) {
+        out.write_all(&square_color(s))?;
+    }
+    // Errors from a buffered writer's last write only show up here.
+    out.flush()
+}
+
+// Replay the trace onto the initial grid, writing a frame after each
+// equal share of the changes. Frames are named frame_0000.ppm and so on.
+fn write_frames(initial: &[Vec<Square>], trace: &[Change], dir: &Path, frames: usize) -> io::Result<()> {
+    let mut grid = initial.to_vec();
+    for frame in 0..frames {
+        let start = frame * trace.len() / frames;
+        let end = (frame + 1) * trace.len() / frames;
+        for &(x, y, s) in trace[start..end].iter() {
+            grid[y][x] = s;
+        }
+        let file = File::create(dir.join(format!("frame_{:04}.ppm", frame)))?;
+        write_ppm(&grid, BufWriter::new(file))?;
+    }
+    Ok(())
+}
+
 // Drop water at given point. Return true if it flows off the bottom.
 fn drop_water(grid: &mut Grid, x: usize, y: usize) -> bool {
     assert_eq!(grid[y][x], Square::Sand);
@@ -91,8 +147,8 @@ fn drop_water(grid: &mut Grid, x: usize, y: usize) -> bool {
     }
 
     // Didn't flow out, so fill row with water.
-    fill(grid, x, y, -1);
-    fill(grid, x+1, y, 1);
+    fill(grid, x, y, -1, &mut vec![]);
+    fill(grid, x+1, y, 1, &mut vec![]);
 
     false
 }
@@ -116,16 +172,114 @@ fn flow(grid: &mut Grid, mut x: usize, y: usize, dir: i32) -> bool {
     grid[y][x] == Square::WetSand
 }
 
-fn fill(grid: &mut Grid, mut x: usize, y: usize, dir: i32) {
+fn fill(grid: &mut Grid, mut x: usize, y: usize, dir: i32, trace: &mut Vec<Change>) {
     while grid[y][x].is_open() {
         grid[y][x] = Square::Water;
+        trace.push((x, y, Square::Water));
         x = (x as i32 + dir) as usize;
     }
 }
 
-fn solve(input: &str) -> (usize, usize) {
-    let veins = parse_input(input);
-    let (min_x, max_x, min_y, max_y) = region_bounds(&veins);
+// Iterative version of drop_water. Each frame on the stack is a pending
+// step of drop_water or flow, and `flows_out` carries the return value
+// of the most recently finished call back to the frame that made it.
+// This needs no call stack, so it works on arbitrarily deep reservoirs.
+// Every square that changes is also recorded in trace, in order.
+#[derive(Clone, Copy, Debug)]
+enum Frame {
+    // Call drop_water at (x, y).
+    Drop(usize, usize),
+    // Water dropped from (x, y) and the drop below it has finished.
+    AfterDrop(usize, usize),
+    // The flow left from (x, y) has finished.
+    AfterLeft(usize, usize),
+    // The flow right from (x, y) has finished; the flow left result is kept.
+    AfterRight(usize, usize, bool),
+    // Call (or continue) flow at (x, y) in direction dir.
+    Flow(isize, usize, isize),
+    // Flow at (x, y) in direction dir dropped water below, which has finished.
+    AfterFlowDrop(usize, usize, isize),
+}
+
+fn drop_water_iter(grid: &mut Grid, x: usize, y: usize, trace: &mut Vec<Change>) -> bool {
+    let width = grid[0].len() as isize;
+    let mut stack = vec![Frame::Drop(x, y)];
+    let mut flows_out = false;
+    while let Some(frame) = stack.pop() {
+        match frame {
+            Frame::Drop(x, y) => {
+                assert_eq!(grid[y][x], Square::Sand);
+                grid[y][x] = Square::WetSand;
+                trace.push((x, y, Square::WetSand));
+                if y+1 >= grid.len() || grid[y+1][x] == Square::WetSand {
+                    flows_out = true;
+                } else if grid[y+1][x] == Square::Sand {
+                    stack.push(Frame::AfterDrop(x, y));
+                    stack.push(Frame::Drop(x, y+1));
+                } else {
+                    stack.push(Frame::AfterLeft(x, y));
+                    stack.push(Frame::Flow(x as isize - 1, y, -1));
+                }
+            },
+            Frame::AfterDrop(x, y) => {
+                if !flows_out {
+                    stack.push(Frame::AfterLeft(x, y));
+                    stack.push(Frame::Flow(x as isize - 1, y, -1));
+                }
+            },
+            Frame::AfterLeft(x, y) => {
+                stack.push(Frame::AfterRight(x, y, flows_out));
+                stack.push(Frame::Flow(x as isize + 1, y, 1));
+            },
+            Frame::AfterRight(x, y, flow_left) => {
+                if flow_left || flows_out {
+                    flows_out = true;
+                } else {
+                    fill(grid, x, y, -1, trace);
+                    fill(grid, x+1, y, 1, trace);
+                }
+            },
+            Frame::Flow(x, y, dir) => {
+                // Running off the side of the grid counts as flowing out.
+                if x < 0 || x >= width {
+                    flows_out = true;
+                    continue;
+                }
+                let x = x as usize;
+                if grid[y][x] != Square::Sand {
+                    // If we found wet sand, it must have flowed out.
+                    flows_out = grid[y][x] == Square::WetSand;
+                    continue;
+                }
+                grid[y][x] = Square::WetSand;
+                trace.push((x, y, Square::WetSand));
+                if y+1 >= grid.len() || grid[y+1][x] == Square::WetSand {
+                    flows_out = true;
+                } else if grid[y+1][x] == Square::Sand {
+                    stack.push(Frame::AfterFlowDrop(x, y, dir));
+                    stack.push(Frame::Drop(x, y+1));
+                } else {
+                    stack.push(Frame::Flow(x as isize + dir, y, dir));
+                }
+            },
+            Frame::AfterFlowDrop(x, y, dir) => {
+                if !flows_out {
+                    stack.push(Frame::Flow(x as isize + dir, y, dir));
+                }
+            },
+        }
+    }
+    flows_out
+}
+
+// Build the grid for the region spanned by the veins (widened to take
+// in every spring), and find where water from each spring enters it.
+// Water from a spring above the region enters at the top row; springs
+// below the region are placed past the bottom row and never flow.
+fn build_grid(veins: &[Vein], springs: &[Spring]) -> (Grid, Vec<Spring>) {
+    let (min_x, max_x, min_y, max_y) = region_bounds(veins);
+    let min_x = springs.iter().map(|s| s.0).fold(min_x, usize::min);
+    let max_x = springs.iter().map(|s| s.0).fold(max_x, usize::max);
     // println!("region top left: ({},{}), bot right", min_x, min_y, max_x, max_y);
 
     let width = max_x - min_x + 1;
@@ -138,36 +292,119 @@ fn solve(input: &str) -> (usize, usize) {
             }
         }
     }
+    let sources = springs.iter()
+        .map(|s| (s.0 - min_x + 1, s.1.saturating_sub(min_y)))
+        .collect();
+    (grid, sources)
+}
+
+// Let water flow from each source in turn. Water that reaches squares
+// already wet from an earlier spring merges with it.
+fn flood(grid: &mut Grid, sources: &[Spring], trace: &mut Vec<Change>) {
+    let height = grid.len();
+    for &(x, y) in sources.iter().filter(|s| s.1 < height) {
+        match grid[y][x] {
+            Square::Sand => if RECURSIVE_ENGINE {
+                drop_water(grid, x, y);
+            } else {
+                drop_water_iter(grid, x, y, trace);
+            },
+            Square::Clay => panic!("spring inside clay"),
+            _ => (),
+        }
+    }
+}
+
+fn count_wet(grid: &[Vec<Square>]) -> (usize, usize) {
+    let wet = grid.iter().flat_map(|row| row.iter())
+        .filter(|s| s.is_wet())
+        .count();
+    let water = grid.iter().flat_map(|row| row.iter())
+        .filter(|&&s| s == Square::Water)
+        .count();
+    (wet, water)
+}
+
+fn solve(input: &str) -> (usize, usize) {
+    let veins = parse_input(input);
+    let (mut grid, sources) = build_grid(&veins, &parse_springs(input));
 
     // println!("initial grid:");
     // show_grid(&grid);
     // println!("--");
 
-    drop_water(&mut grid, 500 - min_x + 1, 0);
+    flood(&mut grid, &sources, &mut vec![]);
 
     if SHOW_FINAL_GRID {
         show_grid(&grid);
     }
 
-    let wet = grid.iter().flat_map(|row| row.iter())
-        .filter(|s| s.is_wet())
-        .count();
-    let water = grid.iter().flat_map(|row| row.iter())
-        .filter(|&&s| s == Square::Water)
-        .count();
+    count_wet(&grid)
+}
 
-    (wet, water)
+// Wet and water counts for each spring flowing on its own.
+fn spring_report(input: &str) -> Vec<(usize, usize)> {
+    let (initial, sources) = build_grid(&parse_input(input), &parse_springs(input));
+    sources.iter().map(|&source| {
+        let mut grid = initial.clone();
+        flood(&mut grid, &[source], &mut vec![]);
+        count_wet(&grid)
+    }).collect()
 }
 
-pub fn run(input: &str) {
+// Save the final ground water as an image at path, or with a frame count,
+// that many images of the water spreading in the directory path.
+fn export(input: &str, path: &Path, frames: Option<usize>) -> io::Result<()> {
+    let (initial, sources) = build_grid(&parse_input(input), &parse_springs(input));
+    let mut grid = initial.clone();
+    let mut trace = vec![];
+    flood(&mut grid, &sources, &mut trace);
+    match frames {
+        Some(frames) => {
+            fs::create_dir_all(path)?;
+            write_frames(&initial, &trace, path, frames)
+        }
+        None => write_ppm(&grid, BufWriter::new(File::create(path)?)),
+    }
+}
+
+pub fn run(input: &str, options: &[String]) {
+    if let Err(err) = run_options(input, Options::new(options)) {
+        eprintln!("{}", err);
+    }
+}
+
+fn run_options(input: &str, options: Options) -> Result<(), String> {
+    match options.mode() {
+        Some(mode @ "image") | Some(mode @ "frames") => {
+            let path = options.get(1).unwrap_or("day17.ppm");
+            let frames = match mode {
+                "frames" => Some(options.parse_or(2, "frame count", 100)?),
+                _ => None,
+            };
+            return export(input, Path::new(path), frames).map_err(|err| format!("export failed: {}", err));
+        }
+        Some(_) => return Err(options.unknown()),
+        None => (),
+    }
+
+    let springs = parse_springs(input);
+    if springs.len() > 1 {
+        for (spring, (wet, water)) in springs.iter().zip(spring_report(input)) {
+            println!("spring at {},{} alone: {} wet, {} water", spring.0, spring.1, wet, water);
+        }
+    }
+
     let (part1, part2) = solve(input);
     println!("the solution to part 1 is {}", part1);
     println!("the solution to part 2 is {}", part2);
+    Ok(())
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::rng::Rng;
 
     const EXAMPLE : &'static str = "\
 x=495, y=2..7
@@ -213,6 +450,100 @@ x=504, y=7..8
         assert_eq!((22, 0), solve(input));
     }
 
+    fn compare_engines(input: &str) {
+        let (grid, sources) = build_grid(&parse_input(input), &[(500, 0)]);
+        let spring_x = sources[0].0;
+        let mut recursive = grid.clone();
+        let mut iterative = grid;
+        assert_eq!(drop_water(&mut recursive, spring_x, 0),
+                   drop_water_iter(&mut iterative, spring_x, 0, &mut vec![]));
+        assert!(recursive == iterative, "engines differ on input:\n{}", input);
+    }
+
+    #[test]
+    fn iterative_engine() {
+        compare_engines(EXAMPLE);
+
+        // Random basins, some of them leaky or overlapping.
+        let mut rng = Rng::new(17);
+        for _ in 0..200 {
+            let mut input = String::from("x=480, y=1..1\nx=520, y=1..1\n");
+            for _ in 0..1 + rng.below(12) {
+                let x0 = 482 + rng.below(30);
+                let x1 = x0 + 2 + rng.below(8);
+                let y0 = 2 + rng.below(40);
+                let y1 = y0 + 1 + rng.below(8);
+                input += &format!("x={}, y={}..{}\n", x0, y0 + rng.below(2), y1);
+                input += &format!("x={}, y={}..{}\n", x1, y0 + rng.below(2), y1);
+                input += &format!("y={}, x={}..{}\n", y1, x0 + rng.below(2), x1);
+            }
+            compare_engines(&input);
+        }
+    }
+
+    #[test]
+    fn deep_reservoir() {
+        // Deep enough to overflow the stack with the recursive engine.
+        let input = "\
+x=480, y=1..1
+x=495, y=49990..50000
+x=505, y=49990..50000
+y=50000, x=495..505
+";
+        assert_eq!((50113, 90), solve(input));
+    }
+
+    #[test]
+    fn image() {
+        let grid = vec![
+            vec![Square::Sand, Square::WetSand],
+            vec![Square::Clay, Square::Water],
+        ];
+        let mut out = vec![];
+        write_ppm(&grid, &mut out).unwrap();
+        let header = b"P6\n2 2\n255\n";
+        assert_eq!(&out[..header.len()], header);
+        assert_eq!(&out[header.len()..], [
+            square_color(Square::Sand), square_color(Square::WetSand),
+            square_color(Square::Clay), square_color(Square::Water),
+        ].concat().as_slice());
+    }
+
+    #[test]
+    fn trace_replay() {
+        let (initial, sources) = build_grid(&parse_input(EXAMPLE), &[(500, 0)]);
+        let mut grid = initial.clone();
+        let mut trace = vec![];
+        flood(&mut grid, &sources, &mut trace);
+        assert_eq!(trace[0], (sources[0].0, 0, Square::WetSand));
+        let mut replay = initial;
+        for &(x, y, s) in trace.iter() {
+            replay[y][x] = s;
+        }
+        assert!(replay == grid);
+    }
+
+    #[test]
+    fn multiple_springs() {
+        let input = "\
+x=490, y=1..1
+x=497, y=3..5
+x=503, y=3..5
+y=5, x=497..503
+x=507, y=3..5
+x=513, y=3..5
+y=5, x=507..513
+spring x=500, y=0
+spring x=501, y=0
+spring x=510, y=0
+spring x=520, y=9
+";
+        assert_eq!(parse_springs(input), vec![(500, 0), (501, 0), (510, 0), (520, 9)]);
+        assert_eq!(parse_springs(EXAMPLE), vec![(500, 0)]);
+        assert_eq!((53, 20), solve(input));
+        assert_eq!(spring_report(input), vec![(26, 10), (26, 10), (26, 10), (0, 0)]);
+    }
+
     #[cfg(feature="test_real_input")]
     #[test]
     fn real_input() {
@@ -1,138 +1,69 @@
-use std::collections::HashMap;
+use std::fs;
+use crate::automaton::*;
+use crate::options::Options;
+
+const LUMBER_RULES: &str = "\
+alphabet = .|#
+. -> | if | >= 3
+| -> # if # >= 3
+# -> # if | >= 1, # >= 1
+# -> .
+";
 
-#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
-enum Square {
-    Open,
-    Trees,
-    Lumberyard,
+fn parse_input(lumber: &Automaton, s: &str) -> Cells {
+    lumber.parse_cells(s).unwrap()
 }
 
-impl From<char> for Square {
-    fn from(c: char) -> Square {
-        match c {
-            '|' => Square::Trees,
-            '#' => Square::Lumberyard,
-            _   => Square::Open,
-        }
-    }
+fn resource_value(lumber: &Automaton, cells: &Cells) -> usize {
+    lumber.count(cells, '|') * lumber.count(cells, '#')
 }
 
-impl From<Square> for char {
-    fn from(s: Square) -> char {
-        match s {
-            Square::Open => '.',
-            Square::Trees => '|',
-            Square::Lumberyard => '#',
-        }
+fn solve(input: &str) -> (usize, usize) {
+    let lumber = Automaton::parse(LUMBER_RULES).unwrap();
+    let initial_grid = parse_input(&lumber, input);
+    const SHOW_STATES: bool = false;
+    if SHOW_STATES {
+        println!("Initial state:\n{}", lumber.show(&initial_grid));
     }
-}
-
-#[derive(Clone, PartialEq, Eq, Hash)]
-struct Grid(Vec<Vec<Square>>);
-
-fn parse_input(s: &str) -> Grid {
-    Grid(s.lines().map(|line| line.chars().map(Square::from).collect()).collect())
-}
-
-fn show_grid(grid: &Grid) -> String {
-    grid.0.iter().map(|row|
-        row.iter().map(|&s| char::from(s)).collect::<String>() + "\n"
-    ).collect()
-}
-
-// Create a table of partial counts.
-// Add extra two rows and two columns to simulate border of open squares.
-fn partial_counts(grid: &Grid, kind: Square) -> Vec<Vec<usize>> {
-    let row_len = grid.0[0].len();
-    let mut p = vec![vec![0; row_len+3]; grid.0.len()+2];
-    for row in 0..grid.0.len() {
-        for col in 0..row_len {
-            let here = (grid.0[row][col] == kind) as usize;
-            p[row+2][col+2] = here + p[row+1][col+2] + p[row+2][col+1] - p[row+1][col+1];
-        }
-        p[row+2][row_len+2] = p[row+2][row_len+1];
+    let state1 = lumber.iterate(&initial_grid, 10);
+    let state2 = lumber.iterate_long(&initial_grid, 1_000_000_000);
+    if SHOW_STATES {
+        println!("Final state:\n{}", lumber.show(&state2));
     }
-    let last_row = p[p.len()-1].clone();
-    p.push(last_row);
-    p
-}
 
-fn get_count(partials: &[Vec<usize>], r: usize, c: usize, h: usize, w: usize) -> usize {
-    partials[r+h][c+w] + partials[r][c] - partials[r+h][c] - partials[r][c+w]
+    (resource_value(&lumber, &state1), resource_value(&lumber, &state2))
 }
 
-fn step(grid: &Grid) -> Grid {
-    let tree_counts = partial_counts(&grid, Square::Trees);
-    let yard_counts = partial_counts(&grid, Square::Lumberyard);
-    let mut next = grid.clone();
-    let row_len = grid.0[0].len();
-    for row in 0..grid.0.len() {
-        for col in 0..row_len {
-            let trees = get_count(&tree_counts, row, col, 3, 3);
-            let yards = get_count(&yard_counts, row, col, 3, 3);
-            match grid.0[row][col] {
-                Square::Open => if trees >= 3 { next.0[row][col] = Square::Trees; },
-                Square::Trees => if yards >= 3 { next.0[row][col] = Square::Lumberyard; },
-                Square::Lumberyard => if trees < 1 || yards < 2 { next.0[row][col] = Square::Open; },
-            }
-        }
-    }
-    next
+// Run the input grid under some other automaton's rules, loaded from a file.
+fn run_rules(input: &str, rules: &str, steps: usize) -> Result<String, String> {
+    let automaton = Automaton::parse(rules)?;
+    let initial = automaton.parse_cells(input)?;
+    Ok(automaton.show(&automaton.iterate_long(&initial, steps)))
 }
 
-fn iterate(initial_state: &Grid, minutes: usize) -> Grid {
-    let mut state = initial_state.clone();
-    for _t in 0..minutes {
-        state = step(&state);
+pub fn run(input: &str, options: &[String]) {
+    if let Err(err) = run_options(input, Options::new(options)) {
+        eprintln!("{}", err);
     }
-    state
 }
 
-fn iterate_long(initial_state: &Grid, minutes: usize) -> Grid {
-    let mut state = initial_state.clone();
-    let mut state_mem = HashMap::new();
-    state_mem.insert(state.clone(), 0);
-    for t in 1.. {
-        state = step(&state);
-        if state_mem.contains_key(&state) {
-            let t0 = state_mem[&state];
-            // println!("found repeat, {} == {}", t0, t);
-            let period = t - t0;
-            let tn = (minutes - t0) % period + t0;
-            // println!("t={} is the same as t={}", minutes, tn);
-            return state_mem.drain().find(|&(_,tk)| tk == tn).unwrap().0;
-        } else {
-            state_mem.insert(state.clone(), t);
+fn run_options(input: &str, options: Options) -> Result<(), String> {
+    match options.mode() {
+        Some(rules_file) => {
+            let steps = options.parse_or(1, "number of steps", 10)?;
+            let grid = fs::read_to_string(rules_file)
+                .map_err(|err| err.to_string())
+                .and_then(|rules| run_rules(input, &rules, steps))
+                .map_err(|err| format!("{}: {}", rules_file, err))?;
+            print!("{}", grid);
+        }
+        None => {
+            let (part1, part2) = solve(input);
+            println!("the solution to part 1 is {}", part1);
+            println!("the solution to part 2 is {}", part2);
         }
     }
-    unreachable!()
-}
-
-fn resource_value(grid: &Grid) -> usize {
-    let trees = grid.0.iter().flat_map(|row| row.iter()).filter(|&&s| s == Square::Trees).count();
-    let yards = grid.0.iter().flat_map(|row| row.iter()).filter(|&&s| s == Square::Lumberyard).count();
-    trees * yards
-}
-
-fn solve(input: &str) -> (usize, usize) {
-    let initial_grid = parse_input(input);
-    const SHOW_STATES: bool = false;
-    if SHOW_STATES {
-        println!("Initial state:\n{}", show_grid(&initial_grid));
-    }
-    let state1 = iterate(&initial_grid, 10);
-    let state2 = iterate_long(&initial_grid, 1_000_000_000);
-    if SHOW_STATES {
-        println!("Final state:\n{}", show_grid(&state2));
-    }
-
-    (resource_value(&state1), resource_value(&state2))
-}
-
-pub fn run(input: &str) {
-    let (part1, part2) = solve(input);
-    println!("the solution to part 1 is {}", part1);
-    println!("the solution to part 2 is {}", part2);
+    Ok(())
 }
 
 #[cfg(test)]
@@ -159,6 +90,109 @@ mod tests {
         assert_eq!(0, part2);
     }
 
+    #[test]
+    fn life() {
+        let rules = "\
+alphabet = .#
+# -> # if # >= 2, # <= 3
+# -> .
+. -> # if # == 3
+";
+        let glider = "\
+.#....
+..#...
+###...
+......
+......
+";
+        assert_eq!(run_rules(glider, rules, 4), Ok("\
+......
+..#...
+...#..
+.###..
+......
+".to_string()));
+    }
+
+    #[test]
+    fn wrapping() {
+        let rules = "\
+alphabet = .#
+edges = wrap
+# -> # if # >= 2, # <= 3
+# -> .
+. -> # if # == 3
+";
+        // The glider moves one square diagonally every 4 steps, so on a
+        // 5x5 torus it is back where it started after 20.
+        let glider = "\
+.#...
+..#..
+###..
+.....
+.....
+";
+        assert_eq!(run_rules(glider, rules, 20), Ok(glider.to_string()));
+        assert_eq!(run_rules(glider, rules, 10), Ok("\
+...##
+.....
+.....
+....#
+..#.#
+".to_string()));
+        // Without wrapping it runs into the corner and becomes a block.
+        let flat = rules.replace("edges = wrap", "edges = empty");
+        assert_eq!(run_rules(glider, &flat, 20), Ok("\
+.....
+.....
+.....
+...##
+...##
+".to_string()));
+
+        // On a grid smaller than the neighborhood, the block around a cell
+        // overlaps itself: a single cell neighbors itself 8 times, and on a
+        // 2x2 grid the cell diagonally opposite a live one sees it 4 times.
+        assert_eq!(run_rules("#\n", rules, 1), Ok(".\n".to_string()));
+        assert_eq!(run_rules("#.\n..\n", "alphabet = .#\nedges = wrap\n. -> # if # == 4\n", 1),
+                   Ok("#.\n.#\n".to_string()));
+        assert!(run_rules(glider, "alphabet = .#\nedges = round\n", 1).is_err());
+    }
+
+    #[test]
+    fn brians_brain() {
+        // Off, on and dying. The two on cells start dying, and the off
+        // cells next to both of them switch on.
+        let rules = "\
+alphabet = .#-
+# -> -
+- -> .
+. -> # if # == 2
+";
+        let start = "\
+......
+......
+..##..
+......
+......
+";
+        let after = run_rules(start, rules, 1).unwrap();
+        assert_eq!(after, "\
+......
+..##..
+..--..
+..##..
+......
+");
+        assert!(run_rules(start, "alphabet = .#\n. -> x\n", 1).is_err());
+        assert_eq!(run_rules(start, "alphabet = .#\n. -> # if # < 0\n", 1),
+                   Err("line 2: # < 0 can never hold".to_string()));
+        assert_eq!(run_rules(start, "alphabet = .#\n. -> # if . >= 2, # > 8\n", 1),
+                   Err("line 2: # > 8 can never hold".to_string()));
+        assert_eq!(run_rules(start, "alphabet = .#\n. -> # if # != 2\n", 1),
+                   Err("line 2: invalid comparison !=".to_string()));
+    }
+
     #[cfg(feature="test_real_input")]
     #[test]
     fn real_input() {
@@ -1,4 +1,6 @@
 use std::collections::HashSet;
+use std::iter::successors;
+use crate::cycle;
 use crate::machine::*;
 
 fn is_r0_test(mach: &Machine, instr: Instr) -> Option<RegType> {
@@ -35,23 +37,24 @@ fn watch_r0(ip: usize, prog: &[Instr], last: bool) -> RegType {
     panic!("register 0 never checked");
 }
 
-fn fast_part2() -> u64  {
-    let mut values = HashSet::new();
-    let mut a = 0u64;
-    let mut last_a = 0;
-    loop {
-        let mut b = a | 0x10000;
-        a = 0x00c1_54d6;
-        while b > 0 {
-            a = (((a + (b & 0xff)) & 0x00ff_ffff) * 65899) & 0x00ff_ffff;
-            b /= 256;
-        }
-        if !values.insert(a) {
-            break;
-        }
-        last_a = a;
+// One pass of the program's outer loop, worked out by hand.
+fn next_a(a: u64) -> u64 {
+    let mut b = a | 0x10000;
+    let mut a = 0x00c1_54d6;
+    while b > 0 {
+        a = (((a + (b & 0xff)) & 0x00ff_ffff) * 65899) & 0x00ff_ffff;
+        b /= 256;
     }
-    last_a
+    a
+}
+
+fn fast_part2() -> u64 {
+    // The last new value is the one just before the values start to repeat.
+    let first = next_a(0);
+    let cycle = cycle::brent(&first, |&a| next_a(a), usize::MAX).unwrap();
+    successors(Some(first), |&a| Some(next_a(a)))
+        .nth(cycle.mu + cycle.lambda - 1)
+        .unwrap()
 }
 
 fn solve(_input: &str) -> (RegType, RegType) {
@@ -81,6 +84,17 @@ eqir 99 0 1
         assert_eq!(99, watch_r0(ip, &prog, false));
     }
 
+    #[test]
+    fn value_cycle() {
+        let first = next_a(0);
+        let by_brent = cycle::brent(&first, |&a| next_a(a), usize::MAX);
+        let by_hash = cycle::hashed(&first, |&a| next_a(a), |&a| a, |_| (), usize::MAX).ok().map(|r| r.cycle);
+        assert!(by_brent.is_some());
+        assert_eq!(by_brent, by_hash);
+        assert_eq!(None, cycle::brent(&first, |&a| next_a(a), 10));
+        assert!(cycle::hashed(&first, |&a| next_a(a), |&a| a, |_| (), 10).is_err());
+    }
+
     #[cfg(feature="test_real_input")]
     #[test]
     fn real_input() {
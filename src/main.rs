@@ -5,8 +5,13 @@ use std::fs::File;
 use std::io::prelude::*;
 use std::process;
 
+mod automaton;
+mod cycle;
 mod machine;
+mod options;
+mod rng;
 mod search;
+mod summed_area;
 
 mod day01;
 mod day02;
@@ -38,6 +43,7 @@ mod day25;
 struct Config {
     target: String,
     input_file: String,
+    options: Vec<String>,
 }
 
 impl Config {
@@ -51,7 +57,8 @@ impl Config {
             Some(arg) => arg,
             None => format!("inputs/{}.txt", target),
         };
-        Ok(Config { target, input_file })
+        let options = args.collect();
+        Ok(Config { target, input_file, options })
     }
 }
 
@@ -78,20 +85,20 @@ fn main() {
         "day02" => day02::run(&input),
         "day03" => day03::run(&input),
         "day04" => day04::run(&input),
-        "day05" => day05::run(&input),
+        "day05" => day05::run(&input, &cfg.options),
         "day06" => day06::run(&input),
-        "day07" => day07::run(&input),
-        "day08" => day08::run(&input),
-        "day09" => day09::run(&input),
-        "day10" => day10::run(&input),
-        "day11" => day11::run(&input),
+        "day07" => day07::run(&input, &cfg.options),
+        "day08" => day08::run(&input, &cfg.options),
+        "day09" => day09::run(&input, &cfg.options),
+        "day10" => day10::run(&input, &cfg.options),
+        "day11" => day11::run(&input, &cfg.options),
         "day12" => day12::run(&input),
-        "day13" => day13::run(&input),
-        "day14" => day14::run(&input),
-        "day15" => day15::run(&input),
+        "day13" => day13::run(&input, &cfg.options),
+        "day14" => day14::run(&input, &cfg.options),
+        "day15" => day15::run(&input, &cfg.options),
         "day16" => day16::run(&input),
-        "day17" => day17::run(&input),
-        "day18" => day18::run(&input),
+        "day17" => day17::run(&input, &cfg.options),
+        "day18" => day18::run(&input, &cfg.options),
         "day19" => day19::run(&input),
         "day20" => day20::run(&input),
         "day21" => day21::run(&input),
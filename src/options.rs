@@ -0,0 +1,63 @@
+use std::str::FromStr;
+
+// Extra command-line options
+//
+// Days that take options get the words after the input file. Most start
+// with a mode word followed by its arguments, like `export FILE 10`; a few
+// take `name value` pairs instead. Problems are returned as messages for
+// the day's run() to print before giving up.
+
+#[derive(Clone, Copy, Debug)]
+pub struct Options<'a>(&'a [String]);
+
+impl<'a> Options<'a> {
+    pub fn new(words: &'a [String]) -> Options<'a> {
+        Options(words)
+    }
+
+    // The first word, which picks a mode.
+    pub fn mode(&self) -> Option<&'a str> {
+        self.get(0)
+    }
+
+    // The options after the mode word.
+    pub fn rest(&self) -> Options<'a> {
+        Options(self.0.get(1..).unwrap_or(&[]))
+    }
+
+    pub fn get(&self, i: usize) -> Option<&'a str> {
+        self.0.get(i).map(String::as_str)
+    }
+
+    pub fn required(&self, i: usize, what: &str) -> Result<&'a str, String> {
+        self.get(i).ok_or_else(|| match self.mode() {
+            Some(mode) if i > 0 => format!("missing {} after {}", what, mode),
+            _ => format!("missing {}", what),
+        })
+    }
+
+    pub fn parse_at<T: FromStr>(&self, i: usize, what: &str) -> Result<T, String> {
+        parse(self.required(i, what)?, what)
+    }
+
+    // Word i parsed as a T, or the default if there is no word i.
+    pub fn parse_or<T: FromStr>(&self, i: usize, what: &str, default: T) -> Result<T, String> {
+        self.get(i).map_or(Ok(default), |s| parse(s, what))
+    }
+
+    // The options as `name value` pairs.
+    pub fn pairs(&self) -> Result<Vec<(&'a str, &'a str)>, String> {
+        self.0.chunks(2).map(|pair| match pair {
+            [name, value] => Ok((name.as_str(), value.as_str())),
+            _ => Err(format!("{} needs a value", pair[0])),
+        }).collect()
+    }
+
+    pub fn unknown(&self) -> String {
+        format!("unknown option {}", self.mode().unwrap_or(""))
+    }
+}
+
+pub fn parse<T: FromStr>(s: &str, what: &str) -> Result<T, String> {
+    s.parse().map_err(|_| format!("invalid {}: {}", what, s))
+}
@@ -0,0 +1,196 @@
+use std::ops::{Add, Range, Sub};
+
+// Summed-area tables
+//
+// After one pass over a grid, the sum of any rectangle of it can be found
+// in constant time: entry [r][c] of the table is the sum of the cells in
+// rows above r and columns left of c, and a rectangle's sum is made of
+// the four entries at its corners. Sums are added before subtracting, so
+// unsigned counts never go below zero along the way.
+//
+// Rectangles are given as ranges of rows and columns. A plain table's
+// `sum` takes ranges inside the grid and does no checking beyond indexing;
+// `clipped_sum` takes ranges that may reach past the edges and leaves out
+// the parts beyond them. A wrapping table instead treats the grid as a
+// torus, so a rectangle that runs off one edge continues from the opposite
+// edge.
+
+#[derive(Clone, Debug)]
+pub struct SummedArea<T> {
+    sums: Vec<Vec<T>>,
+    height: usize,
+    width: usize,
+}
+
+#[derive(Clone, Debug)]
+pub struct WrappingArea<T>(SummedArea<T>);
+
+impl<T> SummedArea<T>
+    where T: Copy + Default + Add<Output=T> + Sub<Output=T>
+{
+    pub fn new(grid: &[Vec<T>]) -> SummedArea<T> {
+        let width = grid.first().map_or(0, |row| row.len());
+        SummedArea::from_fn(grid.len(), width, |row, col| grid[row][col])
+    }
+
+    pub fn from_fn<F>(height: usize, width: usize, cell: F) -> SummedArea<T>
+        where F: Fn(usize, usize) -> T
+    {
+        let mut sums = vec![vec![T::default(); width+1]; height+1];
+        for row in 0..height {
+            for col in 0..width {
+                sums[row+1][col+1] = cell(row, col) + sums[row][col+1] + sums[row+1][col] - sums[row][col];
+            }
+        }
+        SummedArea { sums, height, width }
+    }
+
+    pub fn wrapping(self) -> WrappingArea<T> {
+        WrappingArea(self)
+    }
+
+    pub fn sum(&self, rows: Range<usize>, cols: Range<usize>) -> T {
+        let s = &self.sums;
+        s[rows.end][cols.end] + s[rows.start][cols.start] - s[rows.start][cols.end] - s[rows.end][cols.start]
+    }
+
+    pub fn clipped_sum(&self, rows: Range<isize>, cols: Range<isize>) -> T {
+        self.sum(clip_range(rows, self.height), clip_range(cols, self.width))
+    }
+
+    // The number of cells of the grid inside a clipped rectangle.
+    pub fn clipped_cells(&self, rows: Range<isize>, cols: Range<isize>) -> usize {
+        clip_range(rows, self.height).len() * clip_range(cols, self.width).len()
+    }
+}
+
+impl<T> WrappingArea<T>
+    where T: Copy + Default + Add<Output=T> + Sub<Output=T>
+{
+    pub fn sum(&self, rows: Range<isize>, cols: Range<isize>) -> T {
+        let grid = &self.0;
+        if rows.start >= rows.end || cols.start >= cols.end || grid.height == 0 || grid.width == 0 {
+            return T::default();
+        }
+        // Shift the rectangle to start inside the grid, then count how
+        // many whole copies of the grid it spans along each axis.
+        let (r0, r1) = wrap_range(rows, grid.height);
+        let (c0, c1) = wrap_range(cols, grid.width);
+        self.prefix(r1, c1) + self.prefix(r0, c0) - self.prefix(r0, c1) - self.prefix(r1, c0)
+    }
+
+    // The number of cells a rectangle covers, counting repeats.
+    pub fn cells(&self, rows: Range<isize>, cols: Range<isize>) -> usize {
+        let len = |r: Range<isize>| (r.end - r.start).max(0) as usize;
+        len(rows) * len(cols)
+    }
+
+    // The sum of rows 0..row and columns 0..col of the grid repeated
+    // endlessly to the right and downwards.
+    fn prefix(&self, row: usize, col: usize) -> T {
+        let SummedArea { sums: s, height, width } = &self.0;
+        let (qr, rr) = (row / height, row % height);
+        let (qc, cc) = (col / width, col % width);
+        times(s[*height][*width], qr * qc) + times(s[*height][cc], qr)
+            + times(s[rr][*width], qc) + s[rr][cc]
+    }
+}
+
+fn clip_range(r: Range<isize>, len: usize) -> Range<usize> {
+    let clip = |i: isize| i.max(0).min(len as isize) as usize;
+    let start = clip(r.start);
+    start..clip(r.end).max(start)
+}
+
+fn wrap_range(r: Range<isize>, len: usize) -> (usize, usize) {
+    let start = r.start.rem_euclid(len as isize);
+    (start as usize, (start + r.end - r.start) as usize)
+}
+
+// x added to itself n times, by doubling.
+fn times<T: Copy + Default + Add<Output=T>>(mut x: T, mut n: usize) -> T {
+    let mut total = T::default();
+    while n > 0 {
+        if n & 1 == 1 {
+            total = total + x;
+        }
+        x = x + x;
+        n >>= 1;
+    }
+    total
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn grid() -> Vec<Vec<i32>> {
+        (0..4).map(|r| (0..5).map(|c| (r * 7 + c * 3) % 11 - 5).collect()).collect()
+    }
+
+    // Sum a rectangle cell by cell, wrapping or dropping the cells off
+    // the grid.
+    fn slow_sum(grid: &[Vec<i32>], rows: Range<isize>, cols: Range<isize>, wrap: bool) -> (i32, usize) {
+        let (h, w) = (grid.len() as isize, grid[0].len() as isize);
+        let (mut sum, mut cells) = (0, 0);
+        for r in rows {
+            for c in cols.clone() {
+                if wrap {
+                    sum += grid[r.rem_euclid(h) as usize][c.rem_euclid(w) as usize];
+                    cells += 1;
+                } else if 0 <= r && r < h && 0 <= c && c < w {
+                    sum += grid[r as usize][c as usize];
+                    cells += 1;
+                }
+            }
+        }
+        (sum, cells)
+    }
+
+    #[test]
+    fn inside() {
+        let grid = grid();
+        let table = SummedArea::new(&grid);
+        for r0 in 0..=4 {
+            for r1 in r0..=4 {
+                for c0 in 0..=5 {
+                    for c1 in c0..=5 {
+                        assert_eq!(table.sum(r0..r1, c0..c1),
+                                   slow_sum(&grid, r0 as isize..r1 as isize, c0 as isize..c1 as isize, false).0);
+                    }
+                }
+            }
+        }
+        assert_eq!(table.sum(0..4, 0..5), grid.iter().flatten().sum());
+    }
+
+    #[test]
+    fn clipped_and_wrapping() {
+        let grid = grid();
+        let table = SummedArea::new(&grid);
+        let torus = table.clone().wrapping();
+        for r0 in -6..7 {
+            for r1 in r0-1..r0+11 {
+                for c0 in -7..8 {
+                    for c1 in c0-1..c0+13 {
+                        let (rows, cols) = (r0..r1, c0..c1);
+                        assert_eq!((table.clipped_sum(rows.clone(), cols.clone()),
+                                    table.clipped_cells(rows.clone(), cols.clone())),
+                                   slow_sum(&grid, rows.clone(), cols.clone(), false));
+                        assert_eq!((torus.sum(rows.clone(), cols.clone()), torus.cells(rows.clone(), cols.clone())),
+                                   slow_sum(&grid, rows, cols, true));
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn empty_grid() {
+        let table = SummedArea::<u32>::new(&[]);
+        assert_eq!(table.sum(0..0, 0..0), 0);
+        assert_eq!(table.clipped_sum(-1..2, -1..2), 0);
+        assert_eq!(table.clipped_cells(-1..2, -1..2), 0);
+        assert_eq!(table.wrapping().sum(-1..2, -1..2), 0);
+    }
+}
@@ -0,0 +1 @@
+const STARS = '0 9,1 7,0 3,-2 6,10 2,-4 -6,10 1,8 1,7 -3,11 7,6 -2,3 -4,3 10,-3 5,11 4,7 8,-2 15,0 1,6 8,9 3,3 0,5 -2,2 5,-2 1,4 -2,7 3,6 5,0 -6,0 5,9 14,7 -3,6\n1 9,3 6,0 2,-1 4,9 4,-2 -4,8 2,7 2,7 -2,9 6,5 -1,3 -2,3 9,-2 6,9 4,6 8,-1 13,0 2,6 8,8 2,4 0,4 0,2 6,0 3,5 0,5 2,5 6,0 -4,0 6,7 12,7 -1,5\n2 9,5 5,0 1,0 2,8 6,0 -2,6 3,6 3,7 -1,7 5,4 0,3 0,3 8,-1 7,7 4,5 8,0 11,0 3,6 8,7 1,5 0,3 2,2 7,2 5,6 2,3 1,4 7,0 -2,0 7,5 10,7 1,4\n3 9,7 4,0 0,1 0,7 8,2 0,4 4,5 4,7 0,5 4,3 1,3 2,3 7,0 8,5 4,4 8,1 9,0 4,6 8,6 0,6 0,2 4,2 8,4 7,7 4,1 0,3 8,0 0,0 8,3 8,7 3,3\n4 9,9 3,0 -1,2 -2,6 10,4 2,2 5,4 5,7 1,3 3,2 2,3 4,3 6,1 9,3 4,3 8,2 7,0 5,6 8,5 -1,7 0,1 6,2 9,6 9,8 6,-1 -1,2 9,0 2,0 9,1 6,7 5,2\n5 9,11 2,0 -2,3 -4,5 12,6 4,0 6,3 6,7 2,1 2,1 3,3 6,3 5,2 10,1 4,2 8,3 5,0 6,6 8,4 -2,8 0,0 8,2 10,8 11,9 8,-3 -2,1 10,0 4,0 10,-1 4,7 7,1\n6 9,13 1,0 -3,4 -6,4 14,8 6,-2 7,2 7,7 3,-1 1,0 4,3 8,3 4,3 11,-1 4,1 8,4 3,0 7,6 8,3 -3,9 0,-1 10,2 11,10 13,10 10,-5 -3,0 11,0 6,0 11,-3 2,7 9,0\n';
@@ -55,6 +55,15 @@ function parse_license(license) {
     return get_node();
 }
 
+// Files exported by `cargo run day08 INPUT export FILE.json` hold the
+// tree already parsed; anything else is the raw license numbers.
+function load_license(text) {
+    if (text.trim().startsWith('{')) {
+        return JSON.parse(text);
+    }
+    return parse_license(text.trim());
+}
+
 class TreeView {
     constructor(canvas, node) {
         this.canvas = canvas;
@@ -159,7 +168,7 @@ class TreeView {
 function init() {
     const parse_start = Date.now();
     // const root = parse_license('2 3 0 3 10 11 12 1 1 0 1 99 2 1 1 2');
-    const root = parse_license(LICENSE);
+    const root = (typeof LICENSE_TREE !== 'undefined') ? LICENSE_TREE : parse_license(LICENSE);
     const elapsed = Date.now() - parse_start;
     console.log('license tree ready --', elapsed, 'ms');
     // console.debug(root);
@@ -195,7 +204,7 @@ function init() {
     link_param_control(view, 'background_color');
 
     simple_file_loader('license_file', (new_lic) => {
-        view.root = parse_license(new_lic);
+        view.root = load_license(new_lic);
         view.draw();
     });
 
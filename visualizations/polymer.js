@@ -39,11 +39,15 @@ function collapse(poly, ignore) {
 }
 
 class PolymerView {
-    constructor(canvas, polymer, ignored) {
+    constructor(canvas, polymer, ignored, nodes) {
         this.canvas = canvas;
         this.polymer = polymer;
         this.ignored = ignored;
-        this.run_collapse();
+        if (nodes) {
+            this.nodes = nodes;
+        } else {
+            this.run_collapse();
+        }
 
         this.start_x = START_X;
         this.start_y = START_Y;
@@ -141,7 +145,11 @@ function init() {
     canvas.width = canvas.clientWidth;
     canvas.height = canvas.clientHeight;
 
-    const view = new PolymerView(canvas, polymer, IGNORE_UNIT);
+    // Data saved by `cargo run day05 INPUT tree FILE.js` comes with the
+    // reaction tree already built.
+    const view = (typeof POLYMER_TREE !== 'undefined')
+        ? new PolymerView(canvas, polymer, POLYMER_IGNORE, POLYMER_TREE)
+        : new PolymerView(canvas, polymer, IGNORE_UNIT);
 
     window.requestAnimationFrame(() => {
         canvas.width = canvas.clientWidth;
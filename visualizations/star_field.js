@@ -0,0 +1,129 @@
+const STAR_SIZE = 0.8;
+const MARGIN = 2;
+const FRAME_DELAY = 250;
+
+// night sky
+const BACKGROUND_COLOR = '#000020';
+const STAR_COLOR = '#ffffc0';
+
+// Frames are one per line: the time, then x,y for each star.
+function parse_frames(text) {
+    return text.trim().split('\n').map((line) => {
+        const fields = line.trim().split(' ');
+        const points = fields.slice(1).map((xy) => xy.split(',').map((n) => parseInt(n)));
+        return { time: parseInt(fields[0]), points: points };
+    });
+}
+
+function frame_bounds(frame) {
+    const xs = frame.points.map((p) => p[0]);
+    const ys = frame.points.map((p) => p[1]);
+    return {
+        left: Math.min(...xs), right: Math.max(...xs),
+        top: Math.min(...ys), bottom: Math.max(...ys),
+    };
+}
+
+function frame_area(frame) {
+    const b = frame_bounds(frame);
+    return (b.right - b.left) * (b.bottom - b.top);
+}
+
+class StarView {
+    constructor(canvas, frames) {
+        this.canvas = canvas;
+        this.star_size = STAR_SIZE;
+        this.playing = false;
+        this.background_color = BACKGROUND_COLOR;
+        this.star_color = STAR_COLOR;
+        this.set_frames(frames);
+    }
+
+    set_frames(frames) {
+        this.frames = frames;
+
+        // Start on the frame where the message appears.
+        const areas = frames.map(frame_area);
+        this.frame = areas.indexOf(Math.min(...areas));
+
+        // Keep the scale fixed over all frames so the stars can be seen
+        // coming together.
+        const all = frames.map(frame_bounds);
+        this.bounds = {
+            left: Math.min(...all.map((b) => b.left)) - MARGIN,
+            right: Math.max(...all.map((b) => b.right)) + MARGIN,
+            top: Math.min(...all.map((b) => b.top)) - MARGIN,
+            bottom: Math.max(...all.map((b) => b.bottom)) + MARGIN,
+        };
+    }
+
+    draw() {
+        const ctx = this.canvas.getContext('2d');
+        ctx.fillStyle = this.background_color;
+        ctx.fillRect(0, 0, ctx.canvas.width, ctx.canvas.height);
+
+        const b = this.bounds;
+        const width = b.right - b.left + 1;
+        const height = b.bottom - b.top + 1;
+        const scale = Math.min(ctx.canvas.width / width, ctx.canvas.height / height);
+
+        ctx.save();
+        ctx.translate(ctx.canvas.width/2, ctx.canvas.height/2);
+        ctx.scale(scale, scale);
+        ctx.translate(-(b.left + width/2), -(b.top + height/2));
+        ctx.fillStyle = this.star_color;
+        const offset = (1 - this.star_size) / 2;
+        for (let p of this.frames[this.frame].points) {
+            ctx.fillRect(p[0] + offset, p[1] + offset, this.star_size, this.star_size);
+        }
+        ctx.restore();
+
+        document.getElementById('time').textContent = this.frames[this.frame].time;
+    }
+}
+
+function init() {
+    const canvas = document.getElementById('main');
+    canvas.width = canvas.clientWidth;
+    canvas.height = canvas.clientHeight;
+
+    const view = new StarView(canvas, parse_frames(STARS));
+    const frame_ctl = document.getElementById('frame');
+    frame_ctl.max = view.frames.length - 1;
+
+    window.requestAnimationFrame(() => {
+        canvas.width = canvas.clientWidth;
+        canvas.height = canvas.clientHeight;
+        view.draw();
+    });
+
+    // On window resize, fix canvas size and redraw.
+    window.addEventListener('resize', (event) => {
+        canvas.width = canvas.clientWidth;
+        canvas.height = canvas.clientHeight;
+        view.draw();
+    });
+
+    link_param_control(view, 'frame', (ctl) => parseInt(ctl.value));
+    link_param_control(view, 'star_size', (ctl) => parseFloat(ctl.value));
+    link_param_control(view, 'star_color');
+    link_param_control(view, 'background_color');
+    link_checkbox_control(view, 'playing');
+
+    window.setInterval(() => {
+        if (view.playing) {
+            view.frame = (view.frame + 1) % view.frames.length;
+            frame_ctl.value = view.frame;
+            view.draw();
+        }
+    }, FRAME_DELAY);
+
+    simple_file_loader('frames_file', (text) => {
+        view.set_frames(parse_frames(text));
+        frame_ctl.max = view.frames.length - 1;
+        frame_ctl.value = view.frame;
+        view.draw();
+    });
+}
+
+document.addEventListener('DOMContentLoaded', init);